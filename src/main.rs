@@ -2,13 +2,24 @@
 use std::cmp::max;
 use std::io::Cursor;
 use std::env;
+use std::process;
+use std::time::{Duration, Instant};
 
+mod player;
 mod png;
+mod window;
+use player::Player;
 use png::{
+    buffer::{ChannelLayout, ImageBuffer},
+    color::Color16,
+    composite::BackgroundPolicy,
+    display::DisplayOptions,
+    error::PngError,
     png::Png
 };
 
-use pixel_canvas::{Canvas, Color, input::MouseState};
+use pixel_canvas::Color;
+use window::Window;
 
 const GRAY_SCALE_1BIT: &str = "test_images/grayscale/basn0g01.png";          // ✓
 const GRAY_SCALE_2BIT: &str = "test_images/grayscale/basn0g02.png";          // ✓
@@ -39,28 +50,87 @@ const GRAY_8BIT_BIG: &str = "test_images/grayscale/basn0g08_big.png";
 fn main() {
     let args: Vec<String> = env::args().collect();
 
-    let path = args[1].to_owned().replace("\\", "/");
-    let mut png = Png::read_from_file(&path).unwrap();
+    // --pixel-aspect stretches images with non-square pixels (pHYs) to how they should appear
+    let pixel_aspect = args[1..].iter().any(|a| a == "--pixel-aspect");
+
+    // transparent pixels are drawn over the file's bKGD unless --checkerboard is given
+    let background = match args[1..].iter().any(|a| a == "--checkerboard") {
+        true => BackgroundPolicy::checkerboard(),
+        false => BackgroundPolicy::File
+    };
+
+    let path = match args[1..].iter().find(|a| !a.starts_with("--")) {
+        Some(path) => path.replace("\\", "/"),
+        None => {
+            eprintln!("Usage: {} [--pixel-aspect] [--checkerboard] <file.png>", args[0]);
+            process::exit(1);
+        }
+    };
+    let mut png = match Png::read_from_file(&path) {
+        Ok(png) => png,
+        Err(e) => {
+            eprintln!("Could not read {}: {}", path, e);
+            process::exit(1);
+        }
+    };
 
     png.print(false);
-    png.scale_to_min_width(500);
+    for warning in &png.warnings {
+        eprintln!("Ignored {}", warning);
+    }
+
+    // photos and screenshots are shown upright, along with every APNG frame
+    png.apply_orientation();
+
+    // samples with fewer significant bits than their depth (sBIT) are spread over the full range before they are drawn
+    png.rescale_significant_bits();
+
+    let frames = match display_frames(&png, pixel_aspect, &background) {
+        Ok(frames) => frames,
+        Err(e) => {
+            eprintln!("Could not display {}: {}", path, e);
+            process::exit(1);
+        }
+    };
+    let (width, height) = (frames[0].width() as usize, frames[0].height() as usize);
+
+    // still images are a single frame that never advances
+    let player = match png.animation {
+        Some(animation) => Player::new(png.frames.iter().map(|f| f.control.delay()).collect(), animation.num_plays),
+        None => Player::new(vec![Duration::ZERO], 1)
+    };
+
+    let animated = png.animation.is_some();
+    let name = path.split("/").last().unwrap_or("unknown").to_owned();
+    let title = move |player: &Player| match animated {
+        true => format!("Viewing: {} - {}", name, player.status()),
+        false => format!("Viewing: {}", name)
+    };
+
+    if animated {
+        println!("Space: pause, Left/Right: step, Up/Down: speed");
+    }
+
+    let window = Window::new(width, height, title(&player), player)
+            .input(Player::handle_input);
 
-    let canvas = Canvas::new(png.width as usize, png.height as usize)
-            .title("Viewing: ".to_owned() + &path.split("/").last().unwrap_or("unknown"))
-            //.show_ms(true) - Shows ms / frame in titlebar 
-            .state(MouseState::new())
-            .input(MouseState::handle_input);
+    let mut drawn: Option<usize> = None;
+    let mut last_tick = Instant::now();
 
-    let mut rendered = false;
+    window.render(move |player, image| {
+        let now = Instant::now();
+        player.advance(now - last_tick);
+        last_tick = now;
 
-    canvas.render(move |mouse, image| {
-        if !rendered {
-            let width = image.width() as usize;
+        if drawn != Some(player.frame) {
+            let display = &frames[player.frame];
+            let width = image.width();
             for (y, row) in image.chunks_mut(width).enumerate() {
                 for (x, pixel) in row.iter_mut().enumerate() {
-                    let y = (png.height as usize - y) - 1; // vertically flip b/c (0, 0) of the canvas is the bottom-left
+                    let y = (height - y) - 1; // vertically flip b/c (0, 0) of the canvas is the bottom-left
 
-                    let p: png::png::Color = png.pixels[x+y*width].color.as_rgb();
+                    // 16-bit samples are only reduced to 8 bits for display
+                    let p: png::png::Color = display.get_pixel(x as u32, y as u32).as_color();
 
                     *pixel = Color {
                         r: p.r,
@@ -69,11 +139,58 @@ fn main() {
                     }
                 }
             }
+            drawn = Some(player.frame);
         }
-        rendered = true;
+
+        // the frame index, delay and speed
+        title(player)
     });
 }
 
+// every frame as it is drawn: scaled and composited over the background; still images give one frame.
+// the scaled frames are held to the same memory limit as the decoded ones, checked before anything is resized
+fn display_frames(png: &Png, pixel_aspect: bool, background: &BackgroundPolicy) -> Result<Vec<ImageBuffer<u8>>, PngError> {
+    let canvases = match png.frames.is_empty() {
+        true => vec![png.pixels.clone()],
+        false => png.render_frames()?
+    };
+    let count = canvases.len();
+    let limit = png.max_image_bytes();
+
+    // every frame comes out this size, whatever pHYs says
+    let size = match pixel_aspect {
+        true => png.square_pixel_size(),
+        false => Some((png.width, png.height))
+    };
+    let scalar = size.map_or(1, |(width, _)| Png::min_width_scale(width, 500));
+    let (width, height) = size
+        .and_then(|(width, height)| Some((width.checked_mul(scalar)?, height.checked_mul(scalar)?)))
+        .ok_or(PngError::ImageTooLarge { width: png.width, height: png.height, limit })?;
+
+    // one resized frame at a time, at most RGBA, plus every drawn frame
+    ImageBuffer::<u16>::size_in_bytes(width, height, ChannelLayout::Rgba)
+        .zip(ImageBuffer::<u8>::size_in_bytes(width, height, ChannelLayout::Rgb).and_then(|b| b.checked_mul(count)))
+        .and_then(|(resized, drawn)| resized.checked_add(drawn))
+        .filter(|&b| b <= limit)
+        .ok_or(PngError::ImageTooLarge { width, height, limit })?;
+
+    let mut frames: Vec<ImageBuffer<u8>> = Vec::with_capacity(count);
+    for pixels in canvases {
+        // only the header and color information come along, not the other frames
+        let mut frame = png.with_pixels(pixels);
+
+        if pixel_aspect {
+            frame.correct_pixel_aspect()?;
+        }
+        frame.scale_to_min_width(500)?;
+
+        // gamma correction and compositing only affect what is drawn, not the decoded samples
+        frames.push(frame.composite(&DisplayOptions::default(), background));
+    }
+
+    Ok(frames)
+}
+
 #[allow(unused_imports)]
 mod png_tests {
     use super::*;
@@ -139,4 +256,1661 @@ mod png_tests {
     fn rgb_a_16_bit() {
         assert!(Png::read_from_file(RGB_A_16BIT).is_ok());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn crc32_check_value() {
+        // standard CRC-32 check value
+        assert_eq!(png::helper::crc32(b"123456789"), 0xCBF43926);
+    }
+    #[test]
+    fn chunk_crc_mismatch() {
+        assert!(matches!(Png::read_from_file("test_images/grayscale/xcsn0g01.png"), Err(PngError::CrcMismatch { .. })));
+    }
+    #[test]
+    fn chunk_unlisted_types() {
+        let png = Png::read_from_file("test_images/grayscale/ct1n0g04.png").unwrap();
+
+        assert!(png.chunks.iter().any(|c| c.name == "tEXt"));
+        assert_eq!(png.chunks.last().unwrap().name, "IEND");
+
+        use png::{chunk::Chunk, png::SIGNATURE};
+
+        // unknown ancillary chunks are kept and skipped, unknown critical ones stop the decoder, before or after IDAT
+        let build = |before: &str, after: &str| {
+            let mut bytes: Vec<u8> = SIGNATURE.to_vec();
+            bytes.extend(Chunk::new("IHDR", vec![0, 0, 0, 1, 0, 0, 0, 1, 8, 0, 0, 0, 0]).to_bytes());
+            bytes.extend(Chunk::new(before, vec![1, 2, 3]).to_bytes());
+            bytes.extend(Chunk::new("IDAT", deflate::deflate_bytes_zlib(&[0, 0x80])).to_bytes());
+            bytes.extend(Chunk::new(after, Vec::new()).to_bytes());
+            bytes.extend(Chunk::new("IEND", Vec::new()).to_bytes());
+            bytes
+        };
+
+        let png = Png::from_bytes(&build("prIv", "laTe")).unwrap();
+        assert!(png.chunks.iter().any(|c| c.name == "prIv" && c.data == [1, 2, 3]));
+        assert!(png.chunks.iter().any(|c| c.name == "laTe"));
+
+        assert!(matches!(Png::from_bytes(&build("CRIt", "laTe")), Err(PngError::UnknownCriticalChunk(name)) if name == "CRIt"));
+        assert!(matches!(Png::from_bytes(&build("prIv", "LATE")), Err(PngError::UnknownCriticalChunk(name)) if name == "LATE"));
+    }
+
+    #[test]
+    fn corrupt_signatures() {
+        for path in ["xs1n0g01", "xs2n0g01", "xs4n0g01", "xs7n0g01", "xcrn0g04", "xlfn0g04"] {
+            let result = Png::read_from_file(&format!("test_images/grayscale/{}.png", path));
+
+            assert!(matches!(result, Err(PngError::BadSignature)), "{}: {:?}", path, result.err());
+        }
+    }
+    #[test]
+    fn corrupt_ihdr_crc() {
+        assert!(matches!(Png::read_from_file("test_images/grayscale/xhdn0g08.png"), Err(PngError::CrcMismatch { .. })));
+    }
+    #[test]
+    fn corrupt_color_types() {
+        assert!(matches!(Png::read_from_file("test_images/grayscale/xc1n0g08.png"), Err(PngError::UnsupportedColorType(1))));
+        assert!(matches!(Png::read_from_file("test_images/rgb/xc9n2c08.png"), Err(PngError::UnsupportedColorType(9))));
+    }
+    #[test]
+    fn corrupt_bit_depths() {
+        for path in ["xd0n2c08", "xd3n2c08", "xd9n2c08"] {
+            let result = Png::read_from_file(&format!("test_images/rgb/{}.png", path));
+
+            assert!(matches!(result, Err(PngError::InvalidIhdr(_))), "{}: {:?}", path, result.err());
+        }
+    }
+    #[test]
+    fn corrupt_missing_idat() {
+        assert!(matches!(Png::read_from_file("test_images/grayscale/xdtn0g01.png"), Err(PngError::MissingIdat)));
+    }
+    #[test]
+    fn corrupt_huge_dimensions() {
+        use png::{chunk::Chunk, decoder::DecodeOptions, png::SIGNATURE};
+
+        // a 200000x200000 RGBA header would need 320 GB; it has to fail before anything is allocated
+        let mut ihdr: Vec<u8> = [200_000u32.to_be_bytes(), 200_000u32.to_be_bytes()].concat();
+        ihdr.extend_from_slice(&[8, 6, 0, 0, 0]);
+
+        let mut bytes: Vec<u8> = SIGNATURE.to_vec();
+        bytes.extend(Chunk::new("IHDR", ihdr).to_bytes());
+        bytes.extend(Chunk::new("IDAT", deflate::deflate_bytes_zlib(&[0; 16])).to_bytes());
+        bytes.extend(Chunk::new("IEND", Vec::new()).to_bytes());
+
+        assert!(matches!(Png::from_bytes(&bytes), Err(PngError::ImageTooLarge { width: 200_000, height: 200_000, .. })));
+
+        // the limit is configurable; 32x32 gray takes 2048 bytes of 16-bit samples, and a palette's indices count too
+        let decode = |path: &str, max_image_bytes: usize| Png::from_reader_with(std::fs::File::open(path).unwrap(), &DecodeOptions { max_image_bytes, ..DecodeOptions::default() });
+
+        assert!(decode(GRAY_SCALE_8BIT, 2048).is_ok());
+        assert!(matches!(decode(GRAY_SCALE_8BIT, 2047), Err(PngError::ImageTooLarge { limit: 2047, .. })));
+        assert!(matches!(decode(PALETTED_8BIT, 32 * 32 * 8), Err(PngError::ImageTooLarge { .. })));
+        assert!(decode(PALETTED_8BIT, 32 * 32 * 9).is_ok());
+
+        // scaling for display is checked before it allocates: a 1x1000 image drawn 500 pixels wide, and a pHYs ratio that overflows the width
+        let tall = |width: u32, height: u32, phys: &[u8]| {
+            let mut bytes: Vec<u8> = SIGNATURE.to_vec();
+            bytes.extend(Chunk::new("IHDR", [&width.to_be_bytes()[..], &height.to_be_bytes(), &[8, 0, 0, 0, 0]].concat()).to_bytes());
+            if !phys.is_empty() {
+                bytes.extend(Chunk::new("pHYs", phys.to_vec()).to_bytes());
+            }
+            bytes.extend(Chunk::new("IDAT", deflate::deflate_bytes_zlib(&vec![0; (width as usize + 1) * height as usize])).to_bytes());
+            bytes.extend(Chunk::new("IEND", Vec::new()).to_bytes());
+            Png::from_bytes(&bytes).unwrap()
+        };
+
+        assert!(matches!(display_frames(&tall(1, 1000, &[]), false, &BackgroundPolicy::File), Err(PngError::ImageTooLarge { width: 500, height: 500_000, .. })));
+        assert_eq!(display_frames(&tall(1, 10, &[]), false, &BackgroundPolicy::File).unwrap()[0].height(), 5000);
+
+        let mut stretched = tall(2, 1, &[0, 0, 0, 1, 0xFF, 0xFF, 0xFF, 0xFF, 0]);
+        assert!(matches!(display_frames(&stretched, true, &BackgroundPolicy::File), Err(PngError::ImageTooLarge { .. })));
+        assert!(matches!(stretched.correct_pixel_aspect(), Err(PngError::ImageTooLarge { .. })));
+        assert!(matches!(stretched.scale(u32::MAX as usize), Err(PngError::ImageTooLarge { .. })));
+        assert_eq!((stretched.width, stretched.height), (2, 1));
+    }
+    #[test]
+    fn missing_file() {
+        assert!(matches!(Png::read_from_file("test_images/does_not_exist.png"), Err(PngError::Io(_))));
+    }
+
+    fn colors(path: &str) -> Vec<png::png::Color16> {
+        Png::read_from_file(path).unwrap().pixels.pixels().map(|p| p.color).collect()
+    }
+
+    // a file with extra chunks just before its image data
+    fn with_chunks(path: &str, extra: &[png::chunk::Chunk]) -> Png {
+        let mut chunks = png::chunk::Chunk::from_bytes(&std::fs::read(path).unwrap()).unwrap();
+        let idat = chunks.iter().position(|c| c.name == "IDAT").unwrap();
+        chunks.splice(idat..idat, extra.iter().cloned());
+
+        let mut bytes = png::png::SIGNATURE.to_vec();
+        chunks.iter().for_each(|c| bytes.extend_from_slice(&c.to_bytes()));
+        Png::from_bytes(&bytes).unwrap()
+    }
+
+    #[test]
+    fn split_idat_chunks() {
+        let single = colors("test_images/grayscale/oi1n0g16.png");
+
+        for path in ["oi2n0g16", "oi4n0g16", "oi9n0g16"] {
+            assert!(single == colors(&format!("test_images/grayscale/{}.png", path)), "{}", path);
+        }
+    }
+    #[test]
+    fn interlaced_matches_non_interlaced() {
+        let pairs = [
+            ("grayscale", "0g01"), ("grayscale", "0g02"), ("grayscale", "0g04"), ("grayscale", "0g08"), ("grayscale", "0g16"),
+            ("rgb", "2c08"), ("rgb", "2c16"),
+            ("paletted", "3p01"), ("paletted", "3p02"), ("paletted", "3p04"), ("paletted", "3p08"),
+            ("grayscale_alpha", "4a08"), ("grayscale_alpha", "4a16"),
+            ("rgb_alpha", "6a08"), ("rgb_alpha", "6a16"),
+        ];
+
+        for (dir, name) in pairs {
+            let interlaced = colors(&format!("test_images/{}/basi{}.png", dir, name));
+            let non_interlaced = colors(&format!("test_images/{}/basn{}.png", dir, name));
+
+            assert!(interlaced == non_interlaced, "basi{}", name);
+        }
+    }
+    #[test]
+    fn interlaced_odd_sizes() {
+        for size in ["01", "02", "03", "04", "05", "06", "07", "08", "09", "32", "33", "34", "35", "36", "37", "38", "39", "40"] {
+            let depth = match size { "01" | "02" | "03" | "04" => "1", "05" | "06" | "07" | "08" | "09" => "2", _ => "4" };
+
+            let interlaced = colors(&format!("test_images/paletted/s{}i3p0{}.png", size, depth));
+            let non_interlaced = colors(&format!("test_images/paletted/s{}n3p0{}.png", size, depth));
+
+            assert!(interlaced == non_interlaced, "s{}i3p0{}", size, depth);
+        }
+    }
+
+    #[test]
+    fn lossless_16_bit_samples() {
+        let sample = |path: &str, x: u32, y: u32| {
+            let png = Png::read_from_file(path).unwrap();
+            let c = png.pixels.get_pixel(x, y);
+
+            (c.r, c.g, c.b, c.a)
+        };
+
+        assert_eq!(sample(GRAY_SCALE_16BIT, 1, 0), (2304, 2304, 2304, 0xFFFF));
+        assert_eq!(sample(GRAY_SCALE_16BIT, 17, 5), (41728, 41728, 41728, 0xFFFF));
+        assert_eq!(sample(RGB_16BIT, 1, 0), (63421, 65535, 0, 0xFFFF));
+        assert_eq!(sample(RGB_16BIT, 17, 5), (29596, 54965, 0, 0xFFFF));
+        assert_eq!(sample(GRAY_SCALE_A_16BIT, 17, 5), (56172, 56172, 56172, 21141));
+        assert_eq!(sample(RGB_A_16BIT, 17, 5), (28086, 65535, 0, 21141));
+    }
+    #[test]
+    fn scaled_8_bit_samples() {
+        let png = Png::read_from_file(RGB_8BIT).unwrap();
+
+        for p in png.pixels.pixels() {
+            assert_eq!(Color16::from_8bit(&p.color.as_color()), p.color);
+        }
+    }
+
+    #[test]
+    fn image_buffer_layout() {
+        let png = Png::read_from_file(RGB_A_8BIT).unwrap();
+        let buffer = &png.pixels;
+
+        assert_eq!(buffer.layout(), png::buffer::ChannelLayout::Rgba);
+        assert_eq!(buffer.as_raw().len(), 32 * 32 * 4);
+        assert_eq!(buffer.rows().count(), 32);
+        assert_eq!(buffer.row(5).len(), 32 * 4);
+
+        let c = buffer.get_pixel(17, 5);
+        assert_eq!(&buffer.row(5)[17 * 4..18 * 4], &[c.r, c.g, c.b, c.a]);
+
+        let p = buffer.pixels().nth(17 + 5 * 32).unwrap();
+        assert_eq!(p.pos, (17, 5));
+        assert_eq!(p.color, c);
+    }
+    #[test]
+    fn image_buffer_scale() {
+        let mut png = Png::read_from_file(GRAY_SCALE_8BIT).unwrap();
+        let original = png.pixels.clone();
+
+        png.scale(3).unwrap();
+
+        assert_eq!((png.width, png.height), (96, 96));
+        assert_eq!(png.pixels.get_pixel(53, 17), original.get_pixel(17, 5));
+        assert_eq!(png.pixels.get_pixel(95, 95), original.get_pixel(31, 31));
+    }
+
+    fn test_image_paths() -> Vec<String> {
+        let mut paths: Vec<String> = Vec::new();
+
+        for dir in ["grayscale", "grayscale_alpha", "paletted", "rgb", "rgb_alpha"] {
+            for entry in std::fs::read_dir(format!("test_images/{}", dir)).unwrap() {
+                let name = entry.unwrap().file_name().into_string().unwrap();
+
+                // x*.png are the intentionally corrupt files
+                if !name.starts_with('x') {
+                    paths.push(format!("test_images/{}/{}", dir, name));
+                }
+            }
+        }
+
+        paths.sort();
+        paths
+    }
+
+    #[test]
+    fn encode_round_trip() {
+        // in memory, so parallel test runs never share a file
+        for path in test_image_paths() {
+            let original = Png::read_from_file(&path).unwrap();
+            let mut bytes: Vec<u8> = Vec::new();
+            original.write_to(&mut bytes, &png::encoder::EncodeOptions::default()).unwrap();
+
+            let encoded = Png::from_bytes(&bytes).unwrap();
+
+            assert_eq!((encoded.width, encoded.height, encoded.depth, encoded.color_type), (original.width, original.height, original.depth, original.color_type), "{}", path);
+            assert!(encoded.pixels.pixels().map(|p| p.color).eq(original.pixels.pixels().map(|p| p.color)), "{}", path);
+        }
+    }
+    #[test]
+    fn encode_from_image_buffer() {
+        let original = Png::read_from_file(RGB_A_16BIT).unwrap();
+        let png = Png::from_image_buffer(original.pixels.clone(), 16);
+
+        let bytes = png.to_bytes(&png::encoder::EncodeOptions::default()).unwrap();
+        let chunks = png::chunk::Chunk::from_bytes(&bytes).unwrap();
+
+        assert_eq!(chunks.iter().map(|c| &c.name[..]).collect::<Vec<&str>>(), ["IHDR", "IDAT", "IEND"]);
+    }
+
+    #[test]
+    fn encode_filter_strategies() {
+        use png::{encoder::EncodeOptions, scanline::FilterStrategy};
+
+        let strategies = [FilterStrategy::Fixed(0), FilterStrategy::Fixed(1), FilterStrategy::Fixed(2), FilterStrategy::Fixed(3), FilterStrategy::Fixed(4), FilterStrategy::MinSum, FilterStrategy::BruteForce, FilterStrategy::Entropy];
+
+        for path in [GRAY_SCALE_2BIT, RGB_16BIT, PALETTED_8BIT, RGB_A_8BIT, "test_images/rgb/basi2c08.png"] {
+            let original = Png::read_from_file(path).unwrap();
+            let smallest = original.to_smallest_bytes(&EncodeOptions::default()).unwrap();
+
+            for filter in strategies {
+                let bytes = original.to_bytes(&EncodeOptions { filter, ..EncodeOptions::default() }).unwrap();
+                assert!(smallest.len() <= bytes.len());
+
+                let encoded = Png::from_bytes(&bytes).unwrap();
+
+                assert!(encoded.pixels.pixels().map(|p| p.color).eq(original.pixels.pixels().map(|p| p.color)), "{} {:?}", path, filter);
+            }
+        }
+    }
+    #[test]
+    fn encode_invalid_filter() {
+        use png::{encoder::EncodeOptions, scanline::FilterStrategy};
+
+        let png = Png::read_from_file(RGB_8BIT).unwrap();
+
+        assert!(matches!(png.to_bytes(&EncodeOptions { filter: FilterStrategy::Fixed(5), ..EncodeOptions::default() }), Err(PngError::BadFilterType(5))));
+    }
+
+    #[test]
+    fn zlib_compression_levels() {
+        let stored = colors("test_images/rgb/z00n2c08.png");
+
+        for path in ["z03n2c08", "z06n2c08", "z09n2c08"] {
+            assert!(stored == colors(&format!("test_images/rgb/{}.png", path)), "{}", path);
+        }
+    }
+    #[test]
+    fn zlib_adler32() {
+        assert_eq!(png::zlib::adler32(b"Wikipedia"), 0x11E6_0398);
+        assert_eq!(png::zlib::adler32(&[]), 1);
+    }
+    #[test]
+    fn zlib_streaming_input() {
+        use png::zlib::{inflate_zlib_limited, Inflater};
+
+        let data: Vec<u8> = (0..100_000u32).map(|i| ((i / 3) % 7 * (i % 251) % 256) as u8).collect();
+
+        for level in [deflate::Compression::Fast, deflate::Compression::Default, deflate::Compression::Best] {
+            let compressed = deflate::deflate_bytes_zlib_conf(&data, level);
+            assert_eq!(inflate_zlib_limited(&compressed, data.len()).unwrap(), data);
+
+            // one byte at a time, taking output as it is produced
+            let mut inflater = Inflater::new();
+            let mut inflated: Vec<u8> = Vec::new();
+
+            for byte in compressed.iter() {
+                inflater.push(&[*byte]).unwrap();
+                inflated.extend(inflater.take_output());
+            }
+
+            assert!(inflater.is_finished());
+            assert_eq!(inflated, data);
+        }
+    }
+    #[test]
+    fn zlib_corrupt_streams() {
+        use png::zlib::inflate_zlib_limited;
+
+        let mut compressed = deflate::deflate_bytes_zlib(b"the quick brown fox jumps over the lazy dog");
+
+        assert!(matches!(inflate_zlib_limited(&compressed[..compressed.len() - 1], 1024), Err(PngError::Inflate(_))));
+
+        let last = compressed.len() - 1;
+        compressed[last] ^= 1;
+        assert!(matches!(inflate_zlib_limited(&compressed, 1024), Err(PngError::Inflate(_))));
+
+        assert!(matches!(inflate_zlib_limited(&[0x78, 0x00], 1024), Err(PngError::Inflate(_))));
+    }
+
+    #[test]
+    fn zlib_output_limits() {
+        use png::{colorspace::IccProfile, text::{TextChunk, MAX_TEXT_BYTES}, zlib::inflate_zlib_limited};
+
+        let data = vec![7u8; 1000];
+        let compressed = deflate::deflate_bytes_zlib(&data);
+        assert_eq!(inflate_zlib_limited(&compressed, 1000).unwrap(), data);
+        assert!(matches!(inflate_zlib_limited(&compressed, 999), Err(PngError::Inflate(_))));
+
+        // a few kilobytes of zTXt that would inflate past the text limit
+        let bomb = deflate::deflate_bytes_zlib(&vec![b'a'; MAX_TEXT_BYTES + 1]);
+        assert!(bomb.len() < 64 * 1024);
+
+        let mut ztxt = b"Comment\0\0".to_vec();
+        ztxt.extend_from_slice(&bomb);
+        assert!(matches!(TextChunk::from_chunk("zTXt", &ztxt), Err(PngError::MalformedChunk { .. })));
+
+        // an ICC profile is inflated no further than the size in its header
+        let iccp = |profile: &[u8]| {
+            let mut data = b"profile\0\0".to_vec();
+            data.extend(deflate::deflate_bytes_zlib(profile));
+            IccProfile::from_iccp(&data)
+        };
+        let mut profile = vec![0u8; 200];
+        profile[0..4].copy_from_slice(&200u32.to_be_bytes());
+        assert_eq!(iccp(&profile).unwrap().bytes, profile);
+
+        profile[0..4].copy_from_slice(&100u32.to_be_bytes());
+        assert!(matches!(iccp(&profile), Err(PngError::MalformedChunk { .. })));
+        profile[0..4].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(matches!(iccp(&profile), Err(PngError::MalformedChunk { .. })));
+        assert!(matches!(iccp(&[0, 0]), Err(PngError::MalformedChunk { .. })));
+    }
+
+    // hands out one byte per read, like a slow network stream
+    struct Trickle<'a>(&'a [u8]);
+
+    impl std::io::Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if self.0.is_empty() || buf.is_empty() {
+                return Ok(0)
+            }
+
+            buf[0] = self.0[0];
+            self.0 = &self.0[1..];
+
+            Ok(1)
+        }
+    }
+
+    #[test]
+    fn decoder_rows_match_read_from_file() {
+        use png::decoder::PngDecoder;
+
+        for path in test_image_paths() {
+            let expected = Png::read_from_file(&path).unwrap();
+            let mut decoder = PngDecoder::new(std::fs::File::open(&path).unwrap()).unwrap();
+            let mut rows = 0;
+
+            while let Some(row) = decoder.next_row().unwrap() {
+                let channels = decoder.info().channels();
+                let samples: Vec<u16> = if decoder.info().color_type == 3 { row.scanline.indices().into_iter().map(u16::from).collect() } else { row.scanline.samples() };
+
+                for px in 0..row.pass.width {
+                    let (x, y) = row.position(px);
+                    let s = &samples[(px as usize * channels)..((px as usize + 1) * channels)];
+
+                    if decoder.info().color_type == 3 {
+                        assert_eq!(png::color::Color16::from_8bit(&decoder.info().colors[s[0] as usize]), expected.pixels.get_pixel(x, y), "{}", path);
+                    } else {
+                        assert_eq!(s, &expected.pixels.pixel_samples(x, y)[..channels], "{}", path);
+                    }
+                }
+
+                rows += 1;
+            }
+
+            assert_eq!(rows, expected.filters.len(), "{}", path);
+            assert_eq!(decoder.info().chunks.last().unwrap().name, "IEND", "{}", path);
+        }
+    }
+    #[test]
+    fn decoder_byte_at_a_time() {
+        use png::decoder::PngDecoder;
+
+        for path in ["test_images/rgb_alpha/basi6a16.png", "test_images/rgb/z00n2c08.png", "test_images/grayscale/oi9n0g16.png"] {
+            let bytes = std::fs::read(path).unwrap();
+
+            let mut whole: Vec<Vec<u8>> = Vec::new();
+            PngDecoder::new(&bytes[..]).unwrap().for_each_row(|row| { whole.push(row.scanline.pixel_bytes.clone()); Ok(()) }).unwrap();
+
+            let mut trickled: Vec<Vec<u8>> = Vec::new();
+            let info = PngDecoder::new(Trickle(&bytes)).unwrap().for_each_row(|row| { trickled.push(row.scanline.pixel_bytes.clone()); Ok(()) }).unwrap();
+
+            assert!(whole == trickled, "{}", path);
+            assert!(info.chunks.iter().any(|c| c.name == "IDAT"));
+        }
+    }
+    #[test]
+    fn decoder_corrupt_streams() {
+        use png::decoder::PngDecoder;
+
+        let bytes = std::fs::read(RGB_8BIT).unwrap();
+
+        assert!(matches!(PngDecoder::new(&bytes[..4]), Err(PngError::BadSignature)));
+        assert!(matches!(PngDecoder::new(std::fs::File::open("test_images/grayscale/xdtn0g01.png").unwrap()), Err(PngError::MissingIdat)));
+
+        // cut off inside the image data
+        let result = PngDecoder::new(&bytes[..bytes.len() / 2]).unwrap().for_each_row(|_| Ok(()));
+        assert!(matches!(result, Err(PngError::TruncatedChunk { .. })), "{:?}", result.err());
+
+        // cut off before IEND
+        let result = PngDecoder::new(&bytes[..bytes.len() - 12]).unwrap().for_each_row(|_| Ok(()));
+        assert!(matches!(result, Err(PngError::MissingIend)), "{:?}", result.err());
+    }
+
+    #[test]
+    fn decode_from_bytes_and_readers() {
+        for path in [GRAY_SCALE_1BIT, PALETTED_4BIT, RGB_A_16BIT, "test_images/rgb/basi2c08.png"] {
+            let bytes = std::fs::read(path).unwrap();
+            let from_file = Png::read_from_file(path).unwrap();
+
+            let from_bytes = Png::from_bytes(&bytes).unwrap();
+            let from_reader = Png::from_reader(Cursor::new(bytes.clone())).unwrap();
+            let from_trickle = Png::from_reader(Trickle(&bytes)).unwrap();
+
+            assert_eq!(from_file.filepath.as_deref(), Some(path));
+            assert_eq!(from_bytes.filepath, None);
+
+            for png in [from_bytes, from_reader, from_trickle] {
+                assert!(png.pixels == from_file.pixels, "{}", path);
+                assert_eq!(png.filters, from_file.filters);
+            }
+        }
+
+        assert!(matches!(Png::from_bytes(&[]), Err(PngError::BadSignature)));
+    }
+
+    #[test]
+    fn scanline_strides() {
+        use png::scanline::Stride;
+
+        // (color_type, depth, width) -> (row_length, pixel_length)
+        let cases = [
+            ((3, 1, 1), (1, 1)), ((3, 1, 9), (2, 1)), ((3, 2, 5), (2, 1)), ((3, 2, 7), (2, 1)), ((3, 4, 33), (17, 1)), ((3, 8, 3), (3, 1)),
+            ((0, 1, 35), (5, 1)), ((0, 2, 3), (1, 1)), ((0, 4, 39), (20, 1)), ((0, 16, 3), (6, 2)),
+            ((2, 8, 5), (15, 3)), ((2, 16, 5), (30, 6)), ((4, 8, 2), (4, 2)), ((4, 16, 2), (8, 4)), ((6, 8, 1), (4, 4)), ((6, 16, 5), (40, 8)),
+        ];
+
+        for ((color_type, depth, width), (row_length, pixel_length)) in cases {
+            assert_eq!(Stride::new(color_type, depth, width), Stride { row_length, pixel_length }, "{} {} {}", color_type, depth, width);
+        }
+    }
+    #[test]
+    fn odd_sizes_ignore_padding_bits() {
+        use png::{chunk::Chunk, color::Color, png::SIGNATURE};
+
+        // a 3 pixel 1-bit gray row, 0 1 0, followed by five set padding bits
+        let mut bytes: Vec<u8> = SIGNATURE.to_vec();
+        bytes.extend(Chunk::new("IHDR", vec![0, 0, 0, 3, 0, 0, 0, 1, 1, 0, 0, 0, 0]).to_bytes());
+        bytes.extend(Chunk::new("IDAT", deflate::deflate_bytes_zlib(&[0, 0b0101_1111])).to_bytes());
+        bytes.extend(Chunk::new("IEND", Vec::new()).to_bytes());
+
+        let png = Png::from_bytes(&bytes).unwrap();
+        assert_eq!(png.pixels.as_raw(), [0, 0xFFFF, 0]);
+
+        for size in ["01", "02", "03", "04", "05", "06", "07", "08", "09", "32", "33", "34", "35", "36", "37", "38", "39", "40"] {
+            let depth = match size { "01" | "02" | "03" | "04" => "1", "05" | "06" | "07" | "08" | "09" => "2", _ => "4" };
+            let png = Png::read_from_file(&format!("test_images/paletted/s{}n3p0{}.png", size, depth)).unwrap();
+            let interlaced = Png::read_from_file(&format!("test_images/paletted/s{}i3p0{}.png", size, depth)).unwrap();
+
+            assert_eq!((png.width, png.height), (size.parse().unwrap(), size.parse().unwrap()));
+            assert!(png.pixels.pixels().all(|p| png.colors.iter().any(|c| png::color::Color16::from_8bit(c) == p.color)), "s{}", size);
+            assert!(png.pixels == interlaced.pixels && png.indices == interlaced.indices, "s{}", size);
+        }
+
+        // these are concentric squares, one palette index per ring from the outside in, checked against the raw scanlines;
+        // the last pixel of each row sits right before the padding bits
+        for (path, rings, edge) in [
+            ("s01n3p01", &[0][..], Color { r: 0, g: 0, b: 255, a: 255 }),
+            ("s03n3p01", &[0, 1][..], Color { r: 0, g: 255, b: 0, a: 255 }),
+            ("s07n3p02", &[3, 1, 2, 0][..], Color { r: 0, g: 0, b: 255, a: 255 }),
+            ("s09n3p02", &[0, 3, 2, 1, 0][..], Color { r: 0, g: 255, b: 0, a: 255 }),
+        ] {
+            let png = Png::read_from_file(&format!("test_images/paletted/{}.png", path)).unwrap();
+            let indices = png.indices.as_ref().unwrap();
+            let (w, h) = (png.width, png.height);
+
+            for y in 0..h {
+                for x in 0..w {
+                    let ring = x.min(y).min(w - 1 - x).min(h - 1 - y) as usize;
+                    assert_eq!(indices.pixel_samples(x, y)[0], rings[ring], "{} ({}, {})", path, x, y);
+                }
+
+                assert_eq!(png.pixels.get_pixel(w - 1, y), Color16::from_8bit(&edge), "{} row {}", path, y);
+            }
+        }
+    }
+
+    #[test]
+    fn palette_indices() {
+        for (path, depth) in [(PALETTED_1BIT, 1), (PALETTED_2BIT, 2), (PALETTED_4BIT, 4), (PALETTED_8BIT, 8), ("test_images/paletted/basi3p02.png", 2)] {
+            let png = Png::read_from_file(path).unwrap();
+            let indices = png.indices.as_ref().unwrap();
+
+            assert!(indices.as_raw().iter().all(|&i| (i as usize) < png.palette().len() && (i as u16) < 1 << depth), "{}", path);
+
+            for p in png.indexed_pixels() {
+                let i = p.palette_index.unwrap() as usize;
+
+                assert_eq!(i, indices.pixel_samples(p.pos.0, p.pos.1)[0] as usize);
+                assert_eq!(p.color, png::color::Color16::from_8bit(&png.palette()[i]), "{}", path);
+            }
+        }
+
+        assert!(Png::read_from_file(RGB_8BIT).unwrap().indices.is_none());
+    }
+    #[test]
+    fn palette_editing() {
+        use png::{color::Color, encoder::EncodeOptions};
+
+        let mut png = Png::read_from_file(PALETTED_2BIT).unwrap();
+        let indices = png.indices.clone().unwrap();
+
+        // the same color twice still keeps both indices when re-encoding
+        let gray = Color { r: 128, g: 128, b: 128, a: 255 };
+        png.set_palette(vec![gray; png.palette().len()]).unwrap();
+        assert!(png.pixels.pixels().all(|p| p.color == png::color::Color16::from_8bit(&gray)));
+
+        let encoded = Png::from_bytes(&png.to_bytes(&EncodeOptions::default()).unwrap()).unwrap();
+        assert!(encoded.indices.unwrap() == indices);
+
+        let built = Png::from_indexed(indices.clone(), vec![Color { r: 255, g: 0, b: 0, a: 255 }, gray, gray, gray], 2).unwrap();
+        let encoded = Png::from_bytes(&built.to_bytes(&EncodeOptions::default()).unwrap()).unwrap();
+
+        assert!(encoded.indices.as_ref() == Some(&indices));
+        assert!(encoded.pixels == built.pixels);
+    }
+
+    #[test]
+    fn palette_bounds() {
+        use png::{chunk::Chunk, color::Color, png::SIGNATURE};
+
+        // a 2x1 8-bit paletted image using indices 0 and `index`
+        let image = |palette: &[u8], index: u8| {
+            let mut bytes: Vec<u8> = SIGNATURE.to_vec();
+            bytes.extend(Chunk::new("IHDR", vec![0, 0, 0, 2, 0, 0, 0, 1, 8, 3, 0, 0, 0]).to_bytes());
+            bytes.extend(Chunk::new("PLTE", palette.to_vec()).to_bytes());
+            bytes.extend(Chunk::new("IDAT", deflate::deflate_bytes_zlib(&[0, 0, index])).to_bytes());
+            bytes.extend(Chunk::new("IEND", Vec::new()).to_bytes());
+
+            Png::from_bytes(&bytes)
+        };
+
+        // duplicate entries keep their own indices
+        let png = image(&[10, 10, 10, 10, 10, 10, 20, 20, 20], 2).unwrap();
+        assert_eq!(png.palette().len(), 3);
+        assert_eq!(png.pixels.get_pixel(1, 0).r, 20 * 0x101);
+
+        assert!(matches!(image(&[10, 10, 10, 20, 20, 20], 2), Err(PngError::PaletteIndexOutOfRange { index: 2, entries: 2 })));
+        assert!(matches!(image(&[10, 10, 10, 20, 20], 1), Err(PngError::MalformedChunk { .. })));
+
+        let mut png = Png::read_from_file(PALETTED_2BIT).unwrap();
+        assert!(matches!(png.set_palette(vec![Color { r: 0, g: 0, b: 0, a: 255 }]), Err(PngError::PaletteIndexOutOfRange { .. })));
+        assert!(png::pixel::Pixel::from_palette_index((0, 0), 300, png.palette()).color == png::color::Color16::default());
+
+        // more entries than a 1-bit image can index
+        let mut bytes: Vec<u8> = SIGNATURE.to_vec();
+        bytes.extend(Chunk::new("IHDR", vec![0, 0, 0, 1, 0, 0, 0, 1, 1, 3, 0, 0, 0]).to_bytes());
+        bytes.extend(Chunk::new("PLTE", vec![0; 9]).to_bytes());
+        assert!(matches!(Png::from_bytes(&bytes), Err(PngError::MalformedChunk { .. })));
+    }
+    #[test]
+    fn palette_duplicate_entries() {
+        // tm3n3p02 repeats a palette color with different tRNS alpha
+        let png = Png::read_from_file("test_images/paletted/tm3n3p02.png").unwrap();
+
+        assert_eq!(png.palette().iter().map(|c| c.a).collect::<Vec<u8>>(), [0x00, 0x55, 0xAA, 0xFF]);
+
+        for p in png.indexed_pixels() {
+            assert_eq!(p.color.a, [0x00, 0x55, 0xAA, 0xFF][p.palette_index.unwrap() as usize] as u16 * 0x101);
+        }
+    }
+
+    #[test]
+    fn trns_color_keys() {
+        use png::{buffer::ChannelLayout, decoder::DecodeOptions, encoder::EncodeOptions};
+
+        for (path, layout) in [("test_images/grayscale/tbbn0g04.png", ChannelLayout::GrayAlpha), ("test_images/grayscale/tbwn0g16.png", ChannelLayout::GrayAlpha), ("test_images/rgb/tbbn2c16.png", ChannelLayout::Rgba), ("test_images/rgb/tbrn2c08.png", ChannelLayout::Rgba)] {
+            let png = Png::read_from_file(path).unwrap();
+            assert_eq!(png.pixels.layout(), layout, "{}", path);
+
+            let transparent = png.pixels.pixels().filter(|p| p.color.a == 0).count();
+            assert!(transparent > 0, "{}", path);
+
+            // the key is exposed but not applied
+            let unapplied = Png::from_reader_with(std::fs::File::open(path).unwrap(), &DecodeOptions { apply_transparency: false, ..DecodeOptions::default() }).unwrap();
+            assert!(!unapplied.pixels.layout().has_alpha());
+            assert_eq!(unapplied.pixels.pixels().filter(|p| unapplied.is_color_key(&[p.color.r, p.color.g, p.color.b])).count(), transparent, "{}", path);
+
+            let encoded = Png::from_bytes(&png.to_bytes(&EncodeOptions::default()).unwrap()).unwrap();
+            assert_eq!((encoded.gray_lvl, encoded.truecolor_alpha), (png.gray_lvl, png.truecolor_alpha));
+            assert!(encoded.pixels == png.pixels, "{}", path);
+        }
+
+        // 16-bit keys are kept whole
+        let png = Png::read_from_file("test_images/rgb/tbbn2c16.png").unwrap();
+        assert!(png.truecolor_alpha.unwrap().b > 0xFF || png.truecolor_alpha.unwrap().r > 0xFF || png.truecolor_alpha.unwrap().g > 0xFF);
+
+        for path in ["test_images/grayscale/tp0n0g08.png", "test_images/rgb/tp0n2c08.png"] {
+            assert!(!Png::read_from_file(path).unwrap().pixels.layout().has_alpha(), "{}", path);
+        }
+    }
+
+    #[test]
+    fn gamma_tables() {
+        use png::gamma::{self, GammaTable};
+
+        assert!(GammaTable::new(gamma::SRGB_FILE_GAMMA, gamma::SRGB_DISPLAY_GAMMA).is_identity());
+
+        // linear files are brightened for an sRGB display, and the ends of the range stay put
+        let table = GammaTable::new(1.0, gamma::SRGB_DISPLAY_GAMMA);
+        assert!(table.apply(0x4000) > 0x4000);
+        assert_eq!((table.apply(0), table.apply(0xFFFF)), (0, 0xFFFF));
+
+        for sample in [0u16, 1, 0x1234, 0x8000, 0xFFFF] {
+            assert_eq!(gamma::from_linear(gamma::to_linear(sample, 0.45455), 0.45455), sample);
+            assert!((gamma::linear_to_srgb(gamma::srgb_to_linear(gamma::to_unit(sample))) - gamma::to_unit(sample)).abs() < 1e-9);
+        }
+    }
+    #[test]
+    fn gamma_corrected_display() {
+        // the g* images differ only in file gamma, so they look alike once corrected
+        let mean_difference = |a: &[u8], b: &[u8]| a.iter().zip(b).map(|(x, y)| (*x as f64 - *y as f64).abs()).sum::<f64>() / a.len() as f64;
+
+        for kind in ["grayscale/g{}n0g16", "rgb/g{}n2c08", "paletted/g{}n3p04"] {
+            let reference = Png::read_from_file(&format!("test_images/{}.png", kind.replace("{}", "04"))).unwrap().to_display(&DisplayOptions::default());
+
+            for g in ["03", "05", "07", "10", "25"] {
+                let png = Png::read_from_file(&format!("test_images/{}.png", kind.replace("{}", g))).unwrap();
+
+                let corrected = png.to_display(&DisplayOptions::default());
+                let raw = png.to_display(&DisplayOptions { gamma_correction: false, ..DisplayOptions::default() });
+
+                assert!(mean_difference(corrected.as_raw(), reference.as_raw()) < 8.0, "{} {}", kind, g);
+                assert!(mean_difference(corrected.as_raw(), reference.as_raw()) < mean_difference(raw.as_raw(), reference.as_raw()), "{} {}", kind, g);
+            }
+        }
+
+        // opting out leaves the decoded samples untouched
+        let png = Png::read_from_file("test_images/grayscale/g25n0g16.png").unwrap();
+        let raw = png.to_display(&DisplayOptions { gamma_correction: false, ..DisplayOptions::default() });
+        assert!(png.pixels.pixels().all(|p| raw.get_pixel(p.pos.0, p.pos.1).r == p.color.r >> 8 << 8 | p.color.r >> 8));
+
+        // no gAMA means no correction
+        let png = Png::read_from_file("test_images/rgb/f00n2c08.png").unwrap();
+        assert_eq!(png.file_gamma(), None);
+        assert!(png.gamma_table(&DisplayOptions::default()).is_identity());
+        assert_eq!(png.linear_color(0, 0)[3], 1.0);
+    }
+
+    // a minimal ICC v4 matrix/TRC display profile with a single gamma for every channel
+    fn icc_profile(colorants: [[f64; 3]; 3], gamma: f64) -> Vec<u8> {
+        let s15 = |v: f64| ((v * 65536.0).round() as i32).to_be_bytes();
+
+        let mut tags: Vec<(&[u8], Vec<u8>)> = Vec::new();
+        for (signature, xyz) in [(&b"rXYZ"[..], colorants[0]), (&b"gXYZ"[..], colorants[1]), (&b"bXYZ"[..], colorants[2])] {
+            let mut tag = b"XYZ \0\0\0\0".to_vec();
+            xyz.iter().for_each(|&v| tag.extend_from_slice(&s15(v)));
+            tags.push((signature, tag));
+        }
+        for signature in [&b"rTRC"[..], &b"gTRC"[..], &b"bTRC"[..]] {
+            let mut tag = b"curv\0\0\0\0\0\0\0\x01".to_vec();
+            tag.extend_from_slice(&((gamma * 256.0).round() as u16).to_be_bytes());
+            tags.push((signature, tag));
+        }
+
+        let mut bytes: Vec<u8> = vec![0; 128];
+        bytes[8..12].copy_from_slice(&[4, 0x30, 0, 0]);
+        bytes[12..16].copy_from_slice(b"mntr");
+        bytes[16..20].copy_from_slice(b"RGB ");
+        bytes[20..24].copy_from_slice(b"XYZ ");
+        bytes[36..40].copy_from_slice(b"acsp");
+
+        bytes.extend_from_slice(&(tags.len() as u32).to_be_bytes());
+        let mut offset = 128 + 4 + tags.len() * 12;
+        for (signature, tag) in &tags {
+            bytes.extend_from_slice(signature);
+            bytes.extend_from_slice(&(offset as u32).to_be_bytes());
+            bytes.extend_from_slice(&(tag.len() as u32).to_be_bytes());
+            offset += tag.len();
+        }
+        for (_, tag) in &tags {
+            bytes.extend_from_slice(tag);
+        }
+
+        let size = bytes.len() as u32;
+        bytes[0..4].copy_from_slice(&size.to_be_bytes());
+        bytes
+    }
+
+    #[test]
+    fn chrm_chromaticities() {
+        use png::{colorspace::{self, Chromaticities}, encoder::EncodeOptions};
+
+        // ccwn* carry the sRGB primaries, so they need no conversion beyond gamma
+        for path in ["test_images/rgb/ccwn2c08.png", "test_images/paletted/ccwn3p08.png"] {
+            let png = Png::read_from_file(path).unwrap();
+            let chromaticities = png.chromaticities.unwrap();
+
+            assert!((chromaticities.white.0 - 0.3127).abs() < 1e-4 && (chromaticities.green.1 - 0.6).abs() < 1e-4, "{}", path);
+            assert!(png.color_transform().is_none());
+
+            let managed = png.to_display(&DisplayOptions::default());
+            let unmanaged = png.to_display(&DisplayOptions { color_management: false, ..DisplayOptions::default() });
+            assert!(managed == unmanaged, "{}", path);
+
+            let encoded = Png::from_bytes(&png.to_bytes(&EncodeOptions::default()).unwrap()).unwrap();
+            assert_eq!(encoded.chromaticities, png.chromaticities);
+        }
+
+        // the luminance row of the sRGB matrix
+        let m = colorspace::SRGB.rgb_to_xyz().unwrap();
+        assert!((m[1][0] - 0.2126).abs() < 1e-3 && (m[1][1] - 0.7152).abs() < 1e-3 && (m[1][2] - 0.0722).abs() < 1e-3);
+
+        // wider primaries keep neutrals neutral but pull saturated colors outside sRGB
+        let mut png = Png::from_image_buffer(png::buffer::ImageBuffer::from_raw(3, 1, png::buffer::ChannelLayout::Rgb, vec![0, 0xFFFF, 0, 0x8000, 0x8000, 0x8000, 0x4000, 0xC000, 0x4000]).unwrap(), 16);
+        png.gamma = 45455;
+        png.chromaticities = Some(Chromaticities { green: (0.21, 0.71), ..colorspace::SRGB });
+
+        let display = png.to_display(&DisplayOptions::default());
+        let green = display.get_pixel(0, 0);
+        let gray = display.get_pixel(1, 0);
+
+        assert!(green.r == 0 && green.g == 0xFFFF);
+        assert!(gray.r.abs_diff(gray.g) <= 0x101 && gray.g.abs_diff(gray.b) <= 0x101);
+
+        // a softer green becomes more saturated than its raw samples
+        let unmanaged = png.to_display(&DisplayOptions { color_management: false, ..DisplayOptions::default() });
+        assert!(display.get_pixel(2, 0).r < unmanaged.get_pixel(2, 0).r);
+    }
+    #[test]
+    fn iccp_profiles() {
+        use png::{chunk::Chunk, colorspace::{Curve, IccProfile, MatrixTrc}, encoder::EncodeOptions};
+
+        // sRGB colorants adapted to D50
+        let srgb = [[0.4361, 0.2225, 0.0139], [0.3851, 0.7169, 0.0971], [0.1431, 0.0606, 0.7141]];
+        let profile = IccProfile { name: "test profile".to_owned(), bytes: icc_profile(srgb, 2.2) };
+
+        match profile.matrix_trc().unwrap() {
+            MatrixTrc::Rgb { colorants, curves } => {
+                assert!((colorants[1][1] - 0.7169).abs() < 1e-4);
+                assert!(matches!(curves[0], Curve::Gamma(g) if (g - 2.2).abs() < 0.01));
+            },
+            _ => panic!("expected an RGB profile")
+        }
+
+        let mut png = Png::read_from_file(RGB_8BIT).unwrap();
+        png.icc_profile = Some(profile.clone());
+
+        let encoded = Png::from_bytes(&png.to_bytes(&EncodeOptions::default()).unwrap()).unwrap();
+        assert_eq!(encoded.icc_profile_bytes(), Some(&profile.bytes[..]));
+        assert_eq!(encoded.icc_profile.as_ref().unwrap().name, "test profile");
+
+        // the profile overrides gAMA 1.0, and an sRGB-like profile displays samples nearly as they are
+        let display = encoded.to_display(&DisplayOptions::default());
+        assert!(encoded.pixels.pixels().all(|p| display.get_pixel(p.pos.0, p.pos.1).r.abs_diff(p.color.r) <= 3 * 0x101));
+
+        // profiles that can't be applied are kept but ignored
+        let unusable = IccProfile { name: "lut".to_owned(), bytes: vec![0; 200] };
+        assert!(unusable.matrix_trc().is_none());
+
+        // a tag count past the end of the profile is rejected before the table is searched
+        let mut forged = profile.clone();
+        forged.bytes[128..132].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(forged.matrix_trc().is_none());
+        let most = (forged.bytes.len() as u32 - 132) / 12;
+        forged.bytes[128..132].copy_from_slice(&most.to_be_bytes());
+        assert!(forged.matrix_trc().is_some());
+        forged.bytes[128..132].copy_from_slice(&(most + 1).to_be_bytes());
+        assert!(forged.matrix_trc().is_none());
+
+        assert!(matches!(IccProfile::from_iccp(b"no null"), Err(PngError::MalformedChunk { .. })));
+        assert!(matches!(IccProfile::from_iccp(&Chunk::new("iCCP", b"name\0\x01".to_vec()).data), Err(PngError::MalformedChunk { .. })));
+
+        // a broken cHRM or iCCP leaves the image drawn as if it were not there
+        let plain = Png::read_from_file(RGB_8BIT).unwrap();
+        let broken = with_chunks(RGB_8BIT, &[Chunk::new("cHRM", vec![0; 31]), Chunk::new("iCCP", b"name\0\x01".to_vec())]);
+        assert!(broken.chromaticities.is_none() && broken.icc_profile.is_none() && broken.warnings.len() == 2);
+        assert!(!broken.chunks.iter().any(|c| c.name == "cHRM" || c.name == "iCCP"));
+        assert!(broken.composite(&DisplayOptions::default(), &BackgroundPolicy::File) == plain.composite(&DisplayOptions::default(), &BackgroundPolicy::File));
+
+        // the sRGB curve as a parametric type 3 function
+        let srgb_curve = Curve::Parametric(3, vec![2.4, 1.0 / 1.055, 0.055 / 1.055, 1.0 / 12.92, 0.04045, 0.0, 0.0]);
+        for x in [0.0, 0.02, 0.5, 1.0] {
+            assert!((srgb_curve.eval(x) - png::gamma::srgb_to_linear(x)).abs() < 1e-9);
+        }
+    }
+    #[test]
+    fn text_chunks() {
+        use png::{chunk::Chunk, encoder::EncodeOptions, text::{TextChunk, TextKind}};
+
+        assert!(Png::read_from_file("test_images/grayscale/ct0n0g04.png").unwrap().text().is_empty());
+
+        let plain = Png::read_from_file("test_images/grayscale/ct1n0g04.png").unwrap();
+        assert_eq!(plain.text().len(), 6);
+        assert_eq!(plain.text()[0], TextChunk::new("Title", "PngSuite"));
+        assert_eq!(plain.text()[1].text, "Willem A.J. van Schaik\n(willem@schaik.com)");
+
+        // zTXt decompresses to the same text as ct1n0g04
+        let compressed = Png::read_from_file("test_images/grayscale/ctzn0g04.png").unwrap();
+        assert_eq!(compressed.text()[2].kind, TextKind::Compressed);
+        assert_eq!(compressed.text()[5].text, "Freeware.");
+        assert!(compressed.text()[3].text.starts_with("A compilation of a set of images created to test the\nvarious"));
+
+        for (path, language, title) in [("cten0g04", "en", "Title"), ("ctfn0g04", "fi", "Otsikko"), ("ctgn0g04", "el", "Τίτλος"), ("cthn0g04", "hi", "शीर्षक"), ("ctjn0g04", "ja", "タイトル")] {
+            let png = Png::read_from_file(&format!("test_images/grayscale/{}.png", path)).unwrap();
+            let t = &png.text()[0];
+
+            assert_eq!(t.kind, TextKind::International { compressed: false });
+            assert_eq!((t.keyword.as_str(), t.text.as_str()), ("Title", "PngSuite"));
+            assert_eq!(t.language.as_deref(), Some(language));
+            assert_eq!(t.translated_keyword.as_deref(), Some(title));
+        }
+
+        // every kind round-trips through the encoder, compressed iTXt included
+        let mut png = Png::read_from_file("test_images/grayscale/ctjn0g04.png").unwrap();
+        png.text_chunks.push(TextChunk { kind: TextKind::Compressed, ..TextChunk::new("Comment", "caf\u{e9} ".repeat(50).as_str()) });
+        png.text_chunks.push(TextChunk { kind: TextKind::International { compressed: true }, ..png.text_chunks[3].clone() });
+
+        let encoded = Png::from_bytes(&png.to_bytes(&EncodeOptions::default()).unwrap()).unwrap();
+        assert_eq!(encoded.text(), png.text());
+
+        // keywords must be 1-79 bytes and iTXt must be UTF-8
+        assert!(matches!(TextChunk::from_chunk("tEXt", b"\0text"), Err(PngError::MalformedChunk { .. })));
+        assert!(matches!(TextChunk::from_chunk("tEXt", b"no keyword"), Err(PngError::MalformedChunk { .. })));
+        assert!(matches!(TextChunk::from_chunk("zTXt", b"key\0\0not zlib"), Err(PngError::MalformedChunk { .. })));
+        assert!(matches!(TextChunk::from_chunk("iTXt", b"key\0\0\0en\0\0\xff"), Err(PngError::MalformedChunk { .. })));
+
+        // in a file they are dropped with a warning, and the image and the other text still decode
+        let broken = [Chunk::new("zTXt", b"key\0\0not zlib".to_vec()), Chunk::new("iTXt", b"key\0\0\0en\0\0\xff".to_vec()), Chunk::new("tEXt", b"kept\0text".to_vec())];
+        let png = with_chunks("test_images/grayscale/ct1n0g04.png", &broken);
+        assert_eq!(png.text().len(), 7);
+        assert_eq!(png.text()[6], TextChunk::new("kept", "text"));
+        assert!(!png.chunks.iter().any(|c| c.name == "zTXt" || c.name == "iTXt"));
+        assert_eq!(png.warnings.len(), 2);
+        assert!(png.warnings[0].starts_with("malformed zTXt chunk") && png.warnings[1].starts_with("malformed iTXt chunk"));
+        assert_eq!(png.pixels, plain.pixels);
+    }
+    #[test]
+    fn physical_dimensions() {
+        use png::encoder::EncodeOptions;
+
+        let png = |name: &str| Png::read_from_file(&format!("test_images/rgb/{}.png", name)).unwrap();
+
+        // pHYs with no unit only gives the pixel aspect
+        for (name, ppu, aspect) in [("cdfn2c08", (1, 4), 4.0), ("cdhn2c08", (4, 1), 0.25), ("cdsn2c08", (1, 1), 1.0)] {
+            let p = png(name);
+
+            assert_eq!((p.ppu_x, p.ppu_y, p.unit_spec), (ppu.0, ppu.1, 0), "{}", name);
+            assert_eq!(p.pixel_aspect(), aspect, "{}", name);
+            assert_eq!(p.dpi(), None, "{}", name);
+            assert_eq!(p.display_aspect_ratio(), 1.0, "{}", name);
+        }
+
+        // 1000 pixels per meter
+        let p = png("cdun2c08");
+        let (x, y) = p.dpi().unwrap();
+        assert!((x - 25.4).abs() < 1e-9 && (y - 25.4).abs() < 1e-9);
+        assert_eq!(p.pixel_aspect(), 1.0);
+        assert_eq!(png("basn2c08").pixel_aspect(), 1.0);
+
+        // every cd* image shows as a square
+        for (name, size) in [("cdfn2c08", 32), ("cdhn2c08", 32), ("cdsn2c08", 8), ("cdun2c08", 32)] {
+            let mut p = png(name);
+            let original = p.clone();
+            p.correct_pixel_aspect().unwrap();
+
+            assert_eq!((p.width, p.height), (size, size), "{}", name);
+            assert_eq!(p.pixel_aspect(), 1.0);
+
+            let (sx, sy) = (p.width / original.width, p.height / original.height);
+            assert!(p.pixels.pixels().all(|px| px.color == original.pixels.get_pixel(px.pos.0 / sx, px.pos.1 / sy)), "{}", name);
+        }
+
+        let encoded = Png::from_bytes(&png("cdfn2c08").to_bytes(&EncodeOptions::default()).unwrap()).unwrap();
+        assert_eq!((encoded.ppu_x, encoded.ppu_y, encoded.unit_spec), (1, 4, 0));
+    }
+    #[test]
+    fn background_compositing() {
+        use png::{chunk::Chunk, composite::{self, Background, BackgroundPolicy}, encoder::EncodeOptions, helper::lerp_u8};
+
+        let png = |path: &str| Png::read_from_file(&format!("test_images/{}.png", path)).unwrap();
+
+        // bKGD for every color type, at the source depth and scaled like the samples
+        for (path, background, color) in [
+            ("grayscale/tbbn0g04", Background::Gray(0), Color16::grayscale(0, 0xFFFF)),
+            ("grayscale/tbwn0g16", Background::Gray(0xFFFF), Color16::grayscale(0xFFFF, 0xFFFF)),
+            ("grayscale_alpha/bggn4a16", Background::Gray(0xAB84), Color16::grayscale(0xAB84, 0xFFFF)),
+            ("rgb/tbrn2c08", Background::Rgb(0xFF, 0, 0), Color16 { r: 0xFFFF, g: 0, b: 0, a: 0xFFFF }),
+            ("rgb_alpha/bgyn6a16", Background::Rgb(0xFFFF, 0xFFFF, 0), Color16 { r: 0xFFFF, g: 0xFFFF, b: 0, a: 0xFFFF }),
+        ] {
+            let p = png(path);
+
+            assert_eq!(p.background, Some(background), "{}", path);
+            assert_eq!(p.background_color(), Some(color), "{}", path);
+        }
+
+        let paletted = png("paletted/tbbn3p08");
+        assert_eq!(paletted.background, Some(Background::PaletteIndex(0xF5)));
+        assert_eq!(paletted.background_color(), Some(Color16 { a: 0xFFFF, ..Color16::from_8bit(&paletted.colors[0xF5]) }));
+        assert_eq!(png("grayscale_alpha/bgai4a08").background, None);
+
+        // transparent pixels take the background, and opaque ones are left alone
+        let options = DisplayOptions::default();
+        for path in ["rgb/tbrn2c08", "paletted/tbbn3p08", "rgb_alpha/bgwn6a08", "grayscale_alpha/bgai4a08"] {
+            let p = png(path);
+            let display = p.to_display(&options);
+            let file_background = p.display_color(p.background_color().unwrap_or_else(|| Color16::grayscale(0xFFFF, 0xFFFF)), &options);
+
+            let file = p.composite(&options, &BackgroundPolicy::File);
+            let solid = p.composite(&options, &BackgroundPolicy::Solid(Color16::grayscale(0, 0xFFFF)));
+            let checkerboard = p.composite(&options, &BackgroundPolicy::checkerboard());
+
+            for y in 0..p.height {
+                for x in 0..p.width {
+                    let c = display.get_pixel(x, y);
+
+                    match c.a {
+                        0 => {
+                            assert_eq!(file.get_pixel(x, y), file_background, "{}", path);
+                            assert_eq!(solid.get_pixel(x, y), Color16::grayscale(0, 0xFFFF), "{}", path);
+                            assert_eq!(checkerboard.get_pixel(x, y).r, if (x / 8 + y / 8) % 2 == 0 { 0x9999 } else { 0x6666 }, "{}", path);
+                        },
+                        0xFFFF => assert_eq!(file.get_pixel(x, y), c, "{}", path),
+                        _ => assert_eq!(file.get_pixel(x, y).as_color(), composite::blend(c, file_background).as_color(), "{}", path)
+                    }
+                }
+            }
+        }
+
+        // half coverage rounds to the middle instead of leaning towards the foreground
+        assert_eq!(composite::blend(Color16 { r: 0xFFFF, g: 0, b: 0, a: 0x8000 }, Color16::grayscale(0, 0xFFFF)).r, 0x8000);
+        assert_eq!(lerp_u8(0, 255, 0.5), 128);
+        assert_eq!(lerp_u8(10, 20, 0.99), 20);
+        assert_eq!(lerp_u8(20, 10, 0.99), 10);
+        assert_eq!(png::color::Color { r: 0, g: 0, b: 0, a: 0 }.as_rgb(), png::color::Color::grayscale(255, 255));
+
+        let encoded = Png::from_bytes(&paletted.to_bytes(&EncodeOptions::default()).unwrap()).unwrap();
+        assert_eq!(encoded.background, paletted.background);
+
+        assert!(matches!(Background::from_bkgd(2, 8, &[0, 0]), Err(PngError::MalformedChunk { .. })));
+        let mut small_palette = png("paletted/basn3p01");
+        assert!(matches!(small_palette.read_chunk(&Chunk::new("bKGD", vec![7])), Err(PngError::MalformedChunk { .. })));
+
+        // samples past the bit depth are rejected rather than overflowing when they are scaled
+        assert_eq!(Background::from_bkgd(0, 4, &[0, 15]).unwrap(), Background::Gray(15));
+        assert!(matches!(Background::from_bkgd(0, 4, &[0, 16]), Err(PngError::MalformedChunk { .. })));
+        assert!(matches!(Background::from_bkgd(2, 8, &[0, 0, 1, 0, 0, 0]), Err(PngError::MalformedChunk { .. })));
+
+        let mut bytes: Vec<u8> = png::png::SIGNATURE.to_vec();
+        bytes.extend(Chunk::new("IHDR", vec![0, 0, 0, 1, 0, 0, 0, 1, 8, 0, 0, 0, 0]).to_bytes());
+        bytes.extend(Chunk::new("bKGD", 300u16.to_be_bytes().to_vec()).to_bytes());
+        bytes.extend(Chunk::new("IDAT", deflate::deflate_bytes_zlib(&[0, 0])).to_bytes());
+        bytes.extend(Chunk::new("IEND", Vec::new()).to_bytes());
+
+        // in a file the bKGD is dropped, so the policy's own fallback color is used
+        let dropped = Png::from_bytes(&bytes).unwrap();
+        assert!(dropped.background.is_none() && !dropped.chunks.iter().any(|c| c.name == "bKGD"));
+        assert!(dropped.warnings[0].starts_with("malformed bKGD chunk"));
+        assert!(with_chunks(PALETTED_1BIT, &[Chunk::new("bKGD", vec![7])]).background.is_none());
+    }
+    #[test]
+    fn metadata_chunks() {
+        use png::{chunk::Chunk, decoder::DecodeOptions, encoder::EncodeOptions, metadata::{SignificantBits, StereoLayout, Timestamp}};
+
+        let png = |path: &str| Png::read_from_file(&format!("test_images/{}.png", path)).unwrap();
+
+        for (path, time) in [("cm0n0g04", "2000-01-01 12:34:56 UTC"), ("cm7n0g04", "1970-01-01 00:00:00 UTC"), ("cm9n0g04", "1999-12-31 23:59:59 UTC")] {
+            assert_eq!(png(&format!("grayscale/{}", path)).modified.unwrap().to_string(), time);
+        }
+        assert_eq!(Png::read_from_file(GRAY_SCALE_8BIT).unwrap().modified, None);
+
+        // hIST has one frequency per palette entry
+        let hist = png("paletted/ch1n3p04");
+        assert_eq!(hist.histogram.as_ref().unwrap().len(), hist.colors.len());
+        assert_eq!(hist.histogram.as_ref().unwrap()[..3], [0x40, 0x70, 0x30]);
+        assert_eq!(png("paletted/ch2n3p08").histogram.unwrap().len(), 256);
+
+        // sPLT at both depths
+        for (path, depth) in [("grayscale/ps1n0g08", 8), ("grayscale/ps2n0g08", 16), ("rgb/ps1n2c16", 8), ("rgb/ps2n2c16", 16)] {
+            let p = png(path);
+            let palette = &p.suggested_palettes[0];
+
+            assert_eq!((palette.name.as_str(), palette.depth, palette.entries.len()), ("six-cube", depth, 216), "{}", path);
+            // the 16-bit files store the same 8-bit values
+            assert_eq!((palette.entries[1].b, palette.entries[1].a), (0x33, 0xFF), "{}", path);
+        }
+
+        // sBIT rescales to exact 5-bit and 13-bit levels
+        for (path, bits) in [("rgb/cs5n2c08", 5), ("rgb/cs3n2c16", 13), ("paletted/cs5n3p08", 5)] {
+            let raw = png(path);
+            let rescaled = Png::from_reader_with(std::fs::File::open(format!("test_images/{}.png", path)).unwrap(), &DecodeOptions { rescale_significant_bits: true, ..DecodeOptions::default() }).unwrap();
+
+            assert_eq!(raw.significant_bits.as_ref().unwrap().bits, vec![bits; 3], "{}", path);
+
+            let max = (1u32 << bits) - 1;
+            for (a, b) in raw.pixels.pixels().zip(rescaled.pixels.pixels()) {
+                let expected = |s: u16| (((s as u32 >> (16 - bits)) as f64) * 65535.0 / max as f64).round() as u16;
+
+                assert_eq!((b.color.r, b.color.g, b.color.b), (expected(a.color.r), expected(a.color.g), expected(a.color.b)), "{}", path);
+                assert_eq!(b.color.a, a.color.a);
+            }
+        }
+        assert_eq!(SignificantBits::rescale(0xF8F8, 5), 0xFFFF);
+        assert_eq!(SignificantBits::rescale(0x0707, 5), 0);
+        assert_eq!(SignificantBits::rescale(0x1234, 16), 0x1234);
+
+        // everything survives the encoder
+        let mut p = png("paletted/ch1n3p04");
+        p.modified = Some(Timestamp { year: 2021, month: 6, day: 30, hour: 8, minute: 0, second: 60 });
+        p.stereo = Some(StereoLayout::DivergingFuse);
+        p.suggested_palettes = png("grayscale/ps2n0g08").suggested_palettes;
+
+        let encoded = Png::from_bytes(&p.to_bytes(&EncodeOptions::default()).unwrap()).unwrap();
+        assert_eq!((encoded.modified, encoded.stereo), (p.modified, p.stereo));
+        assert_eq!((&encoded.significant_bits, &encoded.histogram, &encoded.suggested_palettes), (&p.significant_bits, &p.histogram, &p.suggested_palettes));
+
+        let mut p = png("paletted/ch1n3p04");
+        assert_eq!(p.stereo, None);
+        p.read_chunk(&Chunk::new("sTER", vec![0])).unwrap();
+        assert_eq!(p.stereo, Some(StereoLayout::CrossFuse));
+
+        for (name, data) in [
+            ("tIME", vec![7, 208, 13, 1, 0, 0, 0]),
+            ("tIME", vec![7, 208, 1, 1]),
+            ("sBIT", vec![4, 4]),
+            ("sBIT", vec![9, 4, 4]),
+            ("hIST", vec![0; 4]),
+            ("sPLT", b"six-cube\0\x04".to_vec()),
+            ("sPLT", b"six-cube\0\x08\0".to_vec()),
+            ("sPLT", png("grayscale/ps1n0g08").suggested_palettes[0].to_splt()),
+            ("sTER", vec![2]),
+            ("tRNS", vec![0xFF; 16]),
+        ] {
+            let mut p = png("paletted/ch1n3p04");
+            p.suggested_palettes = png("grayscale/ps1n0g08").suggested_palettes;
+
+            assert!(matches!(p.read_chunk(&Chunk::new(name, data.clone())), Err(PngError::MalformedChunk { .. })), "{}", name);
+
+            // in a file the chunk is dropped with a warning and the rest decodes as before
+            let splt = Chunk::new("sPLT", png("grayscale/ps1n0g08").suggested_palettes[0].to_splt());
+            let original = with_chunks("test_images/paletted/ch1n3p04.png", std::slice::from_ref(&splt));
+            let broken = with_chunks("test_images/paletted/ch1n3p04.png", &[splt, Chunk::new(name, data)]);
+
+            assert_eq!(broken.chunks.iter().filter(|c| c.name == name).count(), original.chunks.iter().filter(|c| c.name == name).count(), "{}", name);
+            assert_eq!(broken.warnings.len(), 1, "{}", name);
+            assert!(broken.warnings[0].starts_with(&format!("malformed {} chunk", name)), "{}", name);
+            assert_eq!((&broken.modified, &broken.significant_bits, &broken.histogram, &broken.stereo), (&original.modified, &original.significant_bits, &original.histogram, &original.stereo), "{}", name);
+            assert_eq!(broken.suggested_palettes.len(), 1, "{}", name);
+            assert!(broken.pixels == original.pixels, "{}", name);
+        }
+    }
+
+    // a little-endian TIFF with IFD0, the Exif IFD and the GPS IFD in that order; entries are (tag, type, value bytes)
+    fn tiff(ifds: &[Vec<(u16, u16, Vec<u8>)>]) -> Vec<u8> {
+        let mut offsets = vec![8usize];
+        for ifd in ifds {
+            offsets.push(offsets.last().unwrap() + 2 + ifd.len() * 12 + 4);
+        }
+
+        let mut bytes = b"II*\0\x08\0\0\0".to_vec();
+        let mut data: Vec<u8> = Vec::new();
+
+        for ifd in ifds {
+            bytes.extend_from_slice(&(ifd.len() as u16).to_le_bytes());
+
+            for (tag, kind, value) in ifd {
+                let value = match tag {
+                    0x8769 => (offsets[1] as u32).to_le_bytes().to_vec(),
+                    0x8825 => (offsets[2] as u32).to_le_bytes().to_vec(),
+                    _ => value.clone()
+                };
+                let size = match kind { 3 => 2, 4 => 4, 5 => 8, _ => 1 };
+
+                bytes.extend_from_slice(&tag.to_le_bytes());
+                bytes.extend_from_slice(&kind.to_le_bytes());
+                bytes.extend_from_slice(&((value.len() / size) as u32).to_le_bytes());
+
+                if value.len() <= 4 {
+                    bytes.extend_from_slice(&value);
+                    bytes.extend(vec![0; 4 - value.len()]);
+                } else {
+                    bytes.extend_from_slice(&((offsets[ifds.len()] + data.len()) as u32).to_le_bytes());
+                    data.extend_from_slice(&value);
+                }
+            }
+
+            bytes.extend_from_slice(&[0; 4]);
+        }
+
+        bytes.extend(data);
+        bytes
+    }
+
+    #[test]
+    fn exif_metadata() {
+        use png::{decoder::DecodeOptions, encoder::EncodeOptions, exif::{self, Exif, ExifValue, Ifd, Orientation}};
+
+        let real = Png::read_from_file("test_images/rgb/exif2c08.png").unwrap();
+        let e = real.exif.as_ref().unwrap();
+
+        assert_eq!(e.orientation(), Some(Orientation::Normal));
+        assert_eq!(e.get(Ifd::Primary, 0x8298), Some(&ExifValue::Ascii("2017 Willem van Schaik".to_owned())));
+        assert_eq!(e.get(Ifd::Exif, 0x9000), Some(&ExifValue::Undefined(b"0220".to_vec())));
+        assert_eq!(e.get(Ifd::Primary, 0x011A), Some(&ExifValue::Rational(vec![(72, 1)])));
+        assert!(e.entries.iter().any(|e| e.ifd == Ifd::Thumbnail));
+        assert_eq!((e.make(), e.model(), e.date_time_original(), e.gps()), (None, None, None, None));
+
+        let rationals = |r: &[(u32, u32)]| r.iter().flat_map(|&(n, d)| [n.to_le_bytes(), d.to_le_bytes()].concat()).collect::<Vec<u8>>();
+        let camera = |orientation: u16| tiff(&[
+            vec![
+                (exif::MAKE, 2, b"Phone Maker\0".to_vec()),
+                (exif::MODEL, 2, b"Model 7\0".to_vec()),
+                (exif::ORIENTATION, 3, orientation.to_le_bytes().to_vec()),
+                (exif::EXIF_IFD, 4, vec![]),
+                (exif::GPS_IFD, 4, vec![])
+            ],
+            vec![(exif::DATE_TIME_ORIGINAL, 2, b"2021:06:30 08:15:00\0".to_vec())],
+            vec![
+                (exif::GPS_LATITUDE_REF, 2, b"N\0".to_vec()),
+                (exif::GPS_LATITUDE, 5, rationals(&[(52, 1), (30, 1), (3600, 100)])),
+                (exif::GPS_LONGITUDE_REF, 2, b"W\0".to_vec()),
+                (exif::GPS_LONGITUDE, 5, rationals(&[(1, 1), (15, 1), (0, 1)])),
+                (exif::GPS_ALTITUDE_REF, 1, vec![1]),
+                (exif::GPS_ALTITUDE, 5, rationals(&[(125, 10)]))
+            ]
+        ]);
+
+        let e = Exif::from_exif(&camera(6)).unwrap();
+        assert_eq!((e.make(), e.model(), e.date_time_original()), (Some("Phone Maker"), Some("Model 7"), Some("2021:06:30 08:15:00")));
+        assert_eq!(e.orientation(), Some(Orientation::Rotate90));
+
+        let gps = e.gps().unwrap();
+        assert!((gps.latitude - 52.51).abs() < 1e-9 && (gps.longitude + 1.25).abs() < 1e-9 && gps.altitude == Some(-12.5));
+
+        // where the top-left pixel ends up, for an 8x32 image
+        let source = Png::read_from_file("test_images/rgb/cdfn2c08.png").unwrap();
+        let corner = source.pixels.get_pixel(0, 0);
+
+        for (orientation, (x, y)) in [(1, (0, 0)), (2, (7, 0)), (3, (7, 31)), (4, (0, 31)), (5, (0, 0)), (6, (31, 0)), (7, (31, 7)), (8, (0, 7))] {
+            let mut p = source.clone();
+            p.exif = Some(Exif::from_exif(&camera(orientation)).unwrap());
+            p.apply_orientation();
+
+            let swapped = orientation >= 5;
+            assert_eq!((p.width, p.height), if swapped { (32, 8) } else { (8, 32) }, "{}", orientation);
+            assert_eq!((p.pixels.width(), p.pixels.height()), (p.width, p.height));
+            assert_eq!((p.ppu_x, p.ppu_y), if swapped { (4, 1) } else { (1, 4) }, "{}", orientation);
+            assert_eq!(p.pixels.get_pixel(x, y), corner, "{}", orientation);
+
+            // the orientation is reset, so applying it again changes nothing
+            assert_eq!(p.exif.as_ref().unwrap().orientation(), Some(Orientation::Normal));
+            let again = p.clone();
+            p.apply_orientation();
+            assert!(p.pixels == again.pixels);
+        }
+
+        // eXIf survives the encoder, and the decoder can apply it
+        let mut p = source.clone();
+        p.exif = Some(Exif::from_exif(&camera(8)).unwrap());
+        let bytes = p.to_bytes(&EncodeOptions::default()).unwrap();
+
+        assert_eq!(Png::from_bytes(&bytes).unwrap().exif, p.exif);
+        let upright = Png::from_reader_with(Cursor::new(&bytes), &DecodeOptions { apply_orientation: true, ..DecodeOptions::default() }).unwrap();
+        assert_eq!((upright.width, upright.height), (32, 8));
+        assert_eq!(upright.pixels.get_pixel(0, 7), corner);
+
+        assert!(matches!(Exif::from_exif(b"not tiff"), Err(PngError::MalformedChunk { .. })));
+        assert!(matches!(Exif::from_exif(b"II*\0\xff\0\0\0"), Err(PngError::MalformedChunk { .. })));
+
+        // entries pointing outside the chunk are skipped, and so is whatever follows a cut
+        let mut outside = camera(6);
+        outside[18..22].copy_from_slice(&0xFFFF_FF00u32.to_le_bytes());
+        let e = Exif::from_exif(&outside).unwrap();
+        assert_eq!((e.make(), e.model(), e.orientation()), (None, Some("Model 7"), Some(Orientation::Rotate90)));
+        assert!(e.gps().is_some() && e.date_time_original().is_some());
+
+        let cut = Exif::from_exif(&camera(6)[..40]).unwrap();
+        assert!(cut.entries.is_empty());
+        let cut = Exif::from_exif(&camera(6)[..60]).unwrap();
+        assert_eq!((cut.orientation(), cut.gps()), (Some(Orientation::Rotate90), None));
+
+        // an eXIf that cannot be read at all is dropped and the image decodes without it
+        let p = with_chunks("test_images/rgb/cdfn2c08.png", &[png::chunk::Chunk::new("eXIf", b"not tiff".to_vec())]);
+        assert!(p.exif.is_none() && p.warnings[0].starts_with("malformed eXIf chunk"));
+        assert!(p.pixels == source.pixels);
+    }
+
+    // an RGBA image filled with one color
+    fn solid(width: u32, height: u32, color: Color16) -> png::buffer::ImageBuffer {
+        let mut buffer: png::buffer::ImageBuffer = png::buffer::ImageBuffer::new(width, height, png::buffer::ChannelLayout::Rgba);
+        for y in 0..height {
+            for x in 0..width {
+                buffer.put_pixel(x, y, color);
+            }
+        }
+
+        buffer
+    }
+
+    // the concatenated IDAT data the encoder writes for an image
+    fn idat_data(png: &Png) -> Vec<u8> {
+        let bytes = png.to_bytes(&png::encoder::EncodeOptions::default()).unwrap();
+
+        png::chunk::Chunk::from_bytes(&bytes).unwrap().into_iter().filter(|c| c.name == "IDAT").flat_map(|c| c.data).collect()
+    }
+
+    // an APNG from a default image and frames, each given as its pixels and fcTL fields (x, y, delay_num, delay_den, dispose_op, blend_op);
+    // sequence numbers are filled in, and the default image is the first frame if it has a control of its own
+    #[allow(clippy::type_complexity)]
+    fn apng(default: &Png, default_control: Option<(u16, u16, u8, u8)>, frames: &[(png::buffer::ImageBuffer, (u32, u32, u16, u16, u8, u8))], num_plays: u32) -> Vec<u8> {
+        use png::chunk::Chunk;
+
+        let mut chunks: Vec<Chunk> = png::chunk::Chunk::from_bytes(&default.to_bytes(&png::encoder::EncodeOptions::default()).unwrap()).unwrap();
+        chunks.retain(|c| c.name == "IHDR");
+
+        let num_frames = frames.len() as u32 + default_control.is_some() as u32;
+        chunks.push(Chunk::new("acTL", [num_frames.to_be_bytes(), num_plays.to_be_bytes()].concat()));
+
+        let mut sequence: u32 = 0;
+        let fctl = |sequence: u32, width: u32, height: u32, (x, y, delay_num, delay_den, dispose, blend): (u32, u32, u16, u16, u8, u8)| {
+            let mut data = vec![0u8; 26];
+            data[0..4].copy_from_slice(&sequence.to_be_bytes());
+            for (i, v) in [width, height, x, y].iter().enumerate() {
+                data[4 + i * 4..8 + i * 4].copy_from_slice(&v.to_be_bytes());
+            }
+            data[20..22].copy_from_slice(&delay_num.to_be_bytes());
+            data[22..24].copy_from_slice(&delay_den.to_be_bytes());
+            data[24] = dispose;
+            data[25] = blend;
+
+            Chunk::new("fcTL", data)
+        };
+
+        if let Some((delay_num, delay_den, dispose, blend)) = default_control {
+            chunks.push(fctl(sequence, default.width, default.height, (0, 0, delay_num, delay_den, dispose, blend)));
+            sequence += 1;
+        }
+        chunks.push(Chunk::new("IDAT", idat_data(default)));
+
+        for (pixels, control) in frames {
+            let mut frame = Png::from_image_buffer(pixels.clone(), default.depth);
+            frame.interface = default.interface;
+
+            chunks.push(fctl(sequence, pixels.width(), pixels.height(), *control));
+            sequence += 1;
+
+            // split over two fdAT chunks, as a frame's data may be
+            let data = idat_data(&frame);
+            let (first, second) = data.split_at(data.len() / 2);
+            for part in [first, second] {
+                let mut fdat = sequence.to_be_bytes().to_vec();
+                fdat.extend_from_slice(part);
+                sequence += 1;
+
+                chunks.push(Chunk::new("fdAT", fdat));
+            }
+        }
+
+        chunks.push(Chunk::new("IEND", Vec::new()));
+
+        let mut bytes = png::png::SIGNATURE.to_vec();
+        chunks.iter().for_each(|c| bytes.extend_from_slice(&c.to_bytes()));
+        bytes
+    }
+
+    #[test]
+    fn apng_frames() {
+        use png::apng::{BlendOp, DisposeOp};
+        use std::time::Duration;
+
+        let red = Color16 { r: 0xFFFF, g: 0, b: 0, a: 0xFFFF };
+        let half_blue = Color16 { r: 0, g: 0, b: 0xFFFF, a: 0x8080 };
+        let green = Color16 { r: 0, g: 0xFFFF, b: 0, a: 0xFFFF };
+        let clear = Color16::default();
+
+        for interface in [0, 1] {
+            let mut default = Png::from_image_buffer(solid(4, 4, red), 8);
+            default.interface = interface;
+
+            let bytes = apng(&default, Some((1, 10, 0, 0)), &[
+                (solid(2, 2, half_blue), (1, 1, 50, 0, 2, 1)),
+                (solid(1, 1, green), (0, 0, 1, 4, 1, 0)),
+                (solid(4, 1, clear), (0, 3, 0, 0, 0, 0))
+            ], 3);
+
+            let png = Png::from_bytes(&bytes).unwrap();
+            assert_eq!(png.animation.map(|a| (a.num_frames, a.num_plays)), Some((4, 3)));
+            assert_eq!(png.frames.len(), 4);
+            assert!(png.frames[0].pixels == png.pixels);
+
+            let c = png.frames[1].control;
+            assert_eq!((c.width, c.height, c.x_offset, c.y_offset, c.dispose_op, c.blend_op), (2, 2, 1, 1, DisposeOp::Previous, BlendOp::Over));
+            assert_eq!(png.frames.iter().map(|f| f.control.delay()).collect::<Vec<_>>(), [100, 500, 250, 0].map(Duration::from_millis));
+
+            let rendered = png.render_frames().unwrap();
+            assert!(rendered.iter().all(|r| (r.width(), r.height()) == (4, 4)));
+            assert!(rendered[0].pixels().all(|p| p.color == red));
+
+            // half blue over red
+            let blended = rendered[1].get_pixel(2, 2);
+            assert!(blended.r.abs_diff(0x7F7F) <= 1 && blended.g == 0 && blended.b.abs_diff(0x8080) <= 1 && blended.a == 0xFFFF, "{:?}", blended);
+            assert_eq!(rendered[1].get_pixel(0, 0), red);
+
+            // frame 1 is disposed back to all red before frame 2 is drawn
+            assert_eq!(rendered[2].get_pixel(0, 0), green);
+            assert_eq!(rendered[2].get_pixel(2, 2), red);
+
+            // frame 2 clears its pixel, and frame 3 replaces the bottom row with transparency
+            assert_eq!(rendered[3].get_pixel(0, 0), clear);
+            assert!((0..4).all(|x| rendered[3].get_pixel(x, 3) == clear));
+            assert_eq!(rendered[3].get_pixel(2, 2), red);
+        }
+
+        // without its own fcTL the default image is not part of the animation
+        let default = Png::from_image_buffer(solid(4, 4, red), 8);
+        let png = Png::from_bytes(&apng(&default, None, &[(solid(4, 4, green), (0, 0, 1, 1, 0, 0))], 0)).unwrap();
+        assert_eq!(png.frames.len(), 1);
+        assert!(png.pixels.pixels().all(|p| p.color == red));
+        assert!(png.render_frames().unwrap()[0].pixels().all(|p| p.color == green));
+
+        // still images have no frames
+        assert!(Png::read_from_file(RGB_8BIT).unwrap().frames.is_empty());
+    }
+
+    #[test]
+    fn apng_corrupt() {
+        use png::chunk::Chunk;
+
+        let default = Png::from_image_buffer(solid(4, 4, Color16::default()), 8);
+        let valid = apng(&default, Some((1, 1, 0, 0)), &[(solid(2, 2, Color16::default()), (2, 2, 1, 1, 0, 0))], 0);
+        assert!(Png::from_bytes(&valid).is_ok());
+
+        // rebuilds the file after changing its chunks
+        let edit = |f: &dyn Fn(&mut Vec<Chunk>)| -> Result<Png, PngError> {
+            let mut chunks = Chunk::from_bytes(&valid).unwrap();
+            f(&mut chunks);
+
+            let mut bytes = png::png::SIGNATURE.to_vec();
+            chunks.iter().for_each(|c| bytes.extend_from_slice(&c.to_bytes()));
+            Png::from_bytes(&bytes)
+        };
+        let replace = |chunks: &mut Vec<Chunk>, name: &str, nth: usize, change: &dyn Fn(&mut Vec<u8>)| {
+            let i = chunks.iter().enumerate().filter(|(_, c)| c.name == name).nth(nth).unwrap().0;
+            let mut data = chunks[i].data.clone();
+            change(&mut data);
+            chunks[i] = Chunk::new(name, data);
+        };
+
+        // a broken animation leaves the default image, with one warning saying why
+        let default_pixels = Png::from_bytes(&valid).unwrap().pixels;
+        let falls_back = |result: Result<Png, PngError>, reason: &str| match result {
+            Ok(p) => p.animation.is_none() && p.frames.is_empty() && p.pixels == default_pixels && p.warnings.len() == 1 && p.warnings[0].contains(reason),
+            Err(_) => false
+        };
+
+        // one frame too many announced
+        assert!(falls_back(edit(&|c| replace(c, "acTL", 0, &|d| d[3] = 3)), "malformed acTL"));
+
+        // frames outside the canvas, or empty
+        assert!(falls_back(edit(&|c| replace(c, "fcTL", 1, &|d| d[15] = 3)), "malformed fcTL"));
+        assert!(falls_back(edit(&|c| replace(c, "fcTL", 1, &|d| d[7] = 0)), "malformed fcTL"));
+
+        // the fcTL before IDAT has to cover the whole image
+        assert!(falls_back(edit(&|c| replace(c, "fcTL", 0, &|d| d[7] = 2)), "malformed fcTL"));
+
+        // sequence numbers out of order, and fdAT without fcTL
+        assert!(falls_back(edit(&|c| replace(c, "fdAT", 1, &|d| d[3] = 7)), "malformed fdAT"));
+        assert!(falls_back(edit(&|c| { let i = c.iter().position(|c| c.name == "fcTL" && c.data[3] == 1).unwrap(); c.remove(i); }), "malformed fdAT"));
+
+        // invalid dispose and blend ops
+        assert!(falls_back(edit(&|c| replace(c, "fcTL", 1, &|d| d[24] = 3)), "malformed fcTL"));
+        assert!(falls_back(edit(&|c| replace(c, "fcTL", 1, &|d| d[25] = 2)), "malformed fcTL"));
+
+        // frame data inflates no further than the frame's scanlines
+        let bomb = deflate::deflate_bytes_zlib(&vec![0; 1 << 24]);
+        assert!(falls_back(edit(&|c| replace(c, "fdAT", 0, &|d| { d.truncate(4); d.extend_from_slice(&bomb) })), "could not inflate"));
+
+        // without acTL, or with acTL after IDAT, the file is a still image and nothing is wrong with it
+        let still = edit(&|c| c.retain(|c| c.name != "acTL")).unwrap();
+        assert!(still.animation.is_none() && still.frames.is_empty() && still.warnings.is_empty());
+
+        let late = edit(&|c| { let actl = c.remove(1); c.insert(c.len() - 1, actl); }).unwrap();
+        assert!(late.animation.is_none() && late.frames.is_empty());
+
+        // frames count towards the memory limit: 128 bytes for the default image, again as the first frame, and 32 for the second;
+        // frames that do not fit leave the still image. rendering takes a 128 byte canvas per frame plus two to work with
+        let limited = |max_image_bytes: usize| Png::from_reader_with(Cursor::new(&valid), &png::decoder::DecodeOptions { max_image_bytes, ..png::decoder::DecodeOptions::default() });
+        assert!(falls_back(limited(287), "need more than 287 bytes"));
+        assert!(matches!(limited(288).unwrap().render_frames(), Err(PngError::ImageTooLarge { .. })));
+        assert!(limited(512).unwrap().render_frames().is_ok());
+    }
+
+    #[test]
+    fn apng_transforms() {
+        use png::{chunk::Chunk, decoder::DecodeOptions, encoder::EncodeOptions, exif::{self, Orientation}, metadata::SignificantBits};
+
+        let gradient = |width: u32, height: u32, base: u16| {
+            let mut buffer = solid(width, height, Color16::default());
+            for y in 0..height {
+                for x in 0..width {
+                    buffer.put_pixel(x, y, Color16 { r: base + x as u16 * 0x1111, g: base + y as u16 * 0x2222, b: 0x8888, a: 0xFFFF });
+                }
+            }
+            buffer
+        };
+
+        // a 4x3 canvas with frames off its corners, so a region that did not turn with it would not fit
+        let default = Png::from_image_buffer(gradient(4, 3, 0), 8);
+        let bytes = apng(&default, Some((1, 10, 0, 0)), &[
+            (gradient(2, 1, 0x4444), (1, 1, 1, 10, 1, 0)),
+            (gradient(1, 2, 0x8888), (3, 1, 1, 10, 0, 1))
+        ], 0);
+        let with_chunk = |name: &str, data: Vec<u8>| {
+            let mut chunks = Chunk::from_bytes(&bytes).unwrap();
+            chunks.insert(1, Chunk::new(name, data));
+
+            let mut out = png::png::SIGNATURE.to_vec();
+            chunks.iter().for_each(|c| out.extend_from_slice(&c.to_bytes()));
+            out
+        };
+        let decode = |bytes: &[u8], options: DecodeOptions| Png::from_reader_with(Cursor::new(bytes), &options).unwrap();
+
+        for value in 1..=8u16 {
+            let bytes = with_chunk("eXIf", tiff(&[vec![(exif::ORIENTATION, 3, value.to_le_bytes().to_vec())]]));
+            let orientation = Orientation::from_value(value as u32).unwrap();
+
+            let plain = decode(&bytes, DecodeOptions::default());
+            let upright = decode(&bytes, DecodeOptions { apply_orientation: true, ..DecodeOptions::default() });
+
+            // every frame is shown turned like the default image
+            let expected: Vec<_> = plain.render_frames().unwrap().iter().map(|f| orientation.apply(f)).collect();
+            assert!(upright.render_frames().unwrap() == expected, "orientation {}", value);
+
+            // and it encodes with the default image still the first frame
+            let encoded = Png::from_bytes(&upright.to_bytes(&EncodeOptions::default()).unwrap()).unwrap();
+            let unnumbered = |p: &Png| p.frames.iter().map(|f| (png::apng::FrameControl { sequence_number: 0, ..f.control }, f.pixels.clone())).collect::<Vec<_>>();
+            assert!(unnumbered(&encoded) == unnumbered(&upright) && encoded.frames[0].pixels == encoded.pixels, "orientation {}", value);
+            assert_eq!(Chunk::from_bytes(&upright.to_bytes(&EncodeOptions::default()).unwrap()).unwrap().iter().filter(|c| c.name == "fdAT").count(), 2);
+        }
+
+        // sBIT rescaling reaches the frames as well
+        let bytes = with_chunk("sBIT", vec![3, 3, 3, 8]);
+        let plain = decode(&bytes, DecodeOptions::default());
+        let rescaled = decode(&bytes, DecodeOptions { rescale_significant_bits: true, ..DecodeOptions::default() });
+
+        for (frame, original) in rescaled.frames.iter().zip(&plain.frames) {
+            for p in original.pixels.pixels() {
+                let c = p.color;
+                let expected = Color16 { r: SignificantBits::rescale(c.r, 3), g: SignificantBits::rescale(c.g, 3), b: SignificantBits::rescale(c.b, 3), a: c.a };
+
+                assert_eq!(frame.pixels.get_pixel(p.pos.0, p.pos.1), expected);
+            }
+        }
+        assert!(rescaled.frames[0].pixels == rescaled.pixels);
+    }
+
+    #[test]
+    fn lightweight_copies() {
+        use png::{apng::AnimationOptions, chunk::Chunk};
+
+        // a copy with the same pixels is drawn exactly like the image: gamma, cHRM, bKGD, pHYs and sRGB come along
+        for path in ["rgb/g03n2c08", "paletted/ccwn3p08", "rgb/tbrn2c08", "rgb/cdfn2c08", "rgb/basn2c08"] {
+            let mut p = Png::read_from_file(&format!("test_images/{}.png", path)).unwrap();
+            if path == "rgb/basn2c08" {
+                p.chunks.push(Chunk::new("sRGB", vec![0]));
+            }
+
+            let mut copy = p.with_pixels(p.pixels.clone());
+            let background = BackgroundPolicy::File;
+            assert!(copy.composite(&DisplayOptions::default(), &background) == p.composite(&DisplayOptions::default(), &background), "{}", path);
+            assert!(copy.chunks.iter().all(|c| c.name == "sRGB"));
+
+            copy.correct_pixel_aspect().unwrap();
+            p.correct_pixel_aspect().unwrap();
+            assert_eq!((copy.width, copy.height), (p.width, p.height), "{}", path);
+        }
+
+        // frames, text and EXIF stay behind
+        let mut animated = Png::from_frames(&[solid(2, 2, Color16::default()), solid(2, 2, Color16::grayscale(0xFFFF, 0xFFFF))], &[Duration::ZERO; 2], 8, &AnimationOptions::default()).unwrap();
+        animated.text_chunks.push(png::text::TextChunk::new("Title", "frames"));
+        let copy = animated.with_pixels(solid(3, 1, Color16::default()));
+        assert!(copy.frames.is_empty() && copy.animation.is_none() && copy.text_chunks.is_empty() && copy.exif.is_none());
+        assert_eq!((copy.width, copy.height, copy.depth, copy.color_type), (3, 1, 8, 6));
+    }
+
+    #[test]
+    fn apng_encoder() {
+        use png::apng::{AnimationOptions, BlendOp, DisposeOp};
+        use png::encoder::EncodeOptions;
+        use std::time::Duration;
+
+        let red = Color16 { r: 0xFFFF, g: 0, b: 0, a: 0xFFFF };
+        let blue = Color16 { r: 0, g: 0, b: 0xFFFF, a: 0xFFFF };
+        let green = Color16 { r: 0, g: 0xFFFF, b: 0, a: 0xFFFF };
+        let faint = Color16 { a: 0x8080, ..green };
+
+        let with = |base: &png::buffer::ImageBuffer, pixels: &[(u32, u32, Color16)]| {
+            let mut out = base.clone();
+            pixels.iter().for_each(|&(x, y, c)| out.put_pixel(x, y, c));
+            out
+        };
+
+        // a blue square, then a green one elsewhere, two opaque pixels on a diagonal, a translucent pixel and no change at all
+        let background = solid(8, 8, red);
+        let blue_square = with(&background, &[(1, 1, blue), (2, 1, blue), (1, 2, blue), (2, 2, blue)]);
+        let green_square = with(&background, &[(6, 6, green), (7, 6, green), (6, 7, green), (7, 7, green)]);
+        let diagonal = with(&green_square, &[(0, 0, blue), (1, 1, blue)]);
+        let translucent = with(&diagonal, &[(4, 0, faint)]);
+        let images = [background, blue_square, green_square, diagonal, translucent.clone(), translucent];
+        let delays = [100, 200, 300, 400, 500, 70_000].map(Duration::from_millis);
+
+        for optimize in [true, false] {
+            let options = AnimationOptions { num_plays: 2, optimize };
+            let built = Png::from_frames(&images, &delays, 8, &options).unwrap();
+
+            let bytes = built.to_bytes(&EncodeOptions::default()).unwrap();
+            let decoded = Png::from_bytes(&bytes).unwrap();
+
+            assert_eq!(decoded.animation.map(|a| (a.num_frames, a.num_plays)), Some((6, 2)));
+            assert!(decoded.frames[0].pixels == decoded.pixels);
+            assert_eq!(decoded.frames.iter().map(|f| f.control.delay()).collect::<Vec<_>>(), delays);
+
+            // every frame is shown exactly as given
+            for (rendered, image) in decoded.render_frames().unwrap().iter().zip(&images) {
+                assert!(image.pixels().all(|p| rendered.get_pixel(p.pos.0, p.pos.1) == p.color));
+            }
+
+            let regions: Vec<_> = decoded.frames.iter().map(|f| { let c = f.control; (c.x_offset, c.y_offset, c.width, c.height, c.dispose_op, c.blend_op) }).collect();
+
+            match optimize {
+                false => assert!(regions.iter().all(|&r| r == (0, 0, 8, 8, DisposeOp::None, BlendOp::Source))),
+                true => assert_eq!(regions, [
+                    (0, 0, 8, 8, DisposeOp::None, BlendOp::Source),
+
+                    // restoring the canvas from before the blue square leaves only the green one to draw
+                    (1, 1, 2, 2, DisposeOp::Previous, BlendOp::Over),
+                    (6, 6, 2, 2, DisposeOp::None, BlendOp::Over),
+
+                    // the pixels in between are transparent and left to the canvas
+                    (0, 0, 2, 2, DisposeOp::None, BlendOp::Over),
+
+                    // a translucent pixel has to replace what is there
+                    (4, 0, 1, 1, DisposeOp::None, BlendOp::Source),
+                    (0, 0, 1, 1, DisposeOp::None, BlendOp::Over)
+                ])
+            }
+        }
+
+        // re-encoding a decoded animation keeps its frames
+        let built = Png::from_frames(&images, &delays, 8, &AnimationOptions::default()).unwrap();
+        let decoded = Png::from_bytes(&built.to_bytes(&EncodeOptions::default()).unwrap()).unwrap();
+        let again = Png::from_bytes(&decoded.to_bytes(&EncodeOptions::default()).unwrap()).unwrap();
+        assert_eq!(again.frames, decoded.frames);
+
+        // frames have to match the first in size and layout, with one delay each
+        let encode_error = |result: Result<Png, PngError>| matches!(result, Err(PngError::Encode(_)));
+        assert!(encode_error(Png::from_frames(&[solid(8, 8, red), solid(4, 8, red)], &delays[..2], 8, &AnimationOptions::default())));
+        assert!(encode_error(Png::from_frames(&images, &delays[..2], 8, &AnimationOptions::default())));
+        assert!(encode_error(Png::from_frames(&[], &[], 8, &AnimationOptions::default())));
+    }
+
+    #[test]
+    fn animation_playback() {
+        let ms = Duration::from_millis;
+        let mut player = Player::new(vec![ms(100), ms(0), ms(50)], 2);
+
+        // a delay of 0 moves on immediately; time left over carries into the next frame
+        assert!(!player.advance(ms(99)));
+        assert!(player.advance(ms(1)) && player.frame == 2);
+        assert!(player.advance(ms(60)) && player.frame == 0);
+        assert_eq!(player.status(), "frame 1/3, 100 ms");
+
+        // the second play ends on the last frame
+        assert!(player.advance(ms(140)) && player.frame == 2);
+        assert!(!player.advance(ms(1000)) && player.frame == 2);
+        assert!(player.finished() && player.status().ends_with("finished"));
+
+        // resuming restarts it
+        player.toggle_pause();
+        assert!(!player.finished() && player.frame == 0);
+
+        // steps wrap around and pause
+        player.step(false);
+        assert_eq!((player.frame, player.paused), (2, true));
+        player.step(true);
+        assert_eq!(player.frame, 0);
+        assert!(!player.advance(ms(1000)));
+
+        // double speed halves the delays, within 1/8x-8x
+        player.toggle_pause();
+        player.set_speed(2.0);
+        assert!(player.advance(ms(50)) && player.frame == 2);
+        player.set_speed(100.0);
+        assert_eq!(player.speed, 8.0);
+        assert_eq!(player.status(), "frame 3/3, 50 ms, 8x");
+
+        // still images never advance
+        let mut still = Player::new(vec![Duration::ZERO], 1);
+        assert!(!still.advance(ms(1000)) && still.frame == 0);
+    }
+}
@@ -0,0 +1,133 @@
+use std::time::Duration;
+use glium::glutin::event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent};
+
+// playback speeds are kept between 1/8x and 8x
+const MIN_SPEED: f64 = 0.125;
+const MAX_SPEED: f64 = 8.0;
+
+// which frame of an animation is shown, advanced by the frames' delays [src: https://wiki.mozilla.org/APNG_Specification]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Player {
+    pub delays: Vec<Duration>,
+
+    // 0 loops forever
+    pub num_plays: u32,
+    pub frame: usize,
+    pub paused: bool,
+    pub speed: f64,
+
+    // how long the current frame has been shown, at 1x
+    elapsed: Duration,
+    plays: u32
+}
+
+impl Player {
+    pub fn new(delays: Vec<Duration>, num_plays: u32) -> Player {
+        Player { delays, num_plays, frame: 0, paused: false, speed: 1.0, elapsed: Duration::ZERO, plays: 0 }
+    }
+
+    // the last play has ended on the last frame
+    pub fn finished(&self) -> bool {
+        self.num_plays != 0 && self.plays >= self.num_plays
+    }
+
+    // moves on by dt of wall time, returning whether the frame changed
+    pub fn advance(&mut self, dt: Duration) -> bool {
+        if self.delays.len() < 2 || self.paused || self.finished() {
+            return false
+        }
+
+        let start = self.frame;
+        self.elapsed += dt.mul_f64(self.speed);
+
+        // at most one play per call, so a run of zero delays cannot spin forever
+        for _ in 0..self.delays.len() {
+            let delay = self.delays[self.frame];
+            if self.elapsed < delay {
+                break
+            }
+            self.elapsed -= delay;
+
+            if self.frame + 1 < self.delays.len() {
+                self.frame += 1;
+                continue
+            }
+
+            self.plays += 1;
+            if self.finished() {
+                self.elapsed = Duration::ZERO;
+                break
+            }
+            self.frame = 0;
+        }
+
+        self.frame != start
+    }
+
+    // stepping pauses playback so the frame stays put
+    pub fn step(&mut self, forward: bool) {
+        let n = self.delays.len();
+        if n == 0 {
+            return
+        }
+
+        self.paused = true;
+        self.elapsed = Duration::ZERO;
+        self.frame = match forward {
+            true => (self.frame + 1) % n,
+            false => (self.frame + n - 1) % n
+        };
+    }
+
+    // resuming a finished animation plays it again from the start
+    pub fn toggle_pause(&mut self) {
+        if self.finished() {
+            self.plays = 0;
+            self.frame = 0;
+            self.elapsed = Duration::ZERO;
+            self.paused = false;
+            return
+        }
+
+        self.paused = !self.paused;
+    }
+
+    pub fn set_speed(&mut self, speed: f64) {
+        self.speed = speed.clamp(MIN_SPEED, MAX_SPEED);
+    }
+
+    // e.g. "frame 3/10, 100 ms, 2x, paused"
+    pub fn status(&self) -> String {
+        let mut status = format!("frame {}/{}, {} ms", self.frame + 1, self.delays.len(), self.delays.get(self.frame).map_or(0, |d| d.as_millis()));
+
+        if self.speed != 1.0 {
+            status += &format!(", {}x", self.speed);
+        }
+        if self.paused {
+            status += ", paused";
+        } else if self.finished() {
+            status += ", finished";
+        }
+
+        status
+    }
+
+    // space pauses, left and right step a frame, up and down double or halve the speed
+    pub fn handle_input(player: &mut Player, event: &Event<()>) -> bool {
+        let key = match event {
+            Event::WindowEvent { event: WindowEvent::KeyboardInput { input: KeyboardInput { state: ElementState::Pressed, virtual_keycode: Some(key), .. }, .. }, .. } => *key,
+            _ => return false
+        };
+
+        match key {
+            VirtualKeyCode::Space => player.toggle_pause(),
+            VirtualKeyCode::Right => player.step(true),
+            VirtualKeyCode::Left => player.step(false),
+            VirtualKeyCode::Up => player.set_speed(player.speed * 2.0),
+            VirtualKeyCode::Down => player.set_speed(player.speed / 2.0),
+            _ => return false
+        }
+
+        true
+    }
+}
@@ -0,0 +1,519 @@
+use std::{convert::TryInto, time::Duration};
+use super::{
+    buffer::{ChannelLayout, ImageBuffer},
+    color::Color16,
+    decoder::Row,
+    error::PngError,
+    interlace::{self, Pass},
+    png::Png,
+    scanline::Scanline,
+    zlib
+};
+
+// acTL [src: https://wiki.mozilla.org/APNG_Specification]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AnimationControl {
+    pub num_frames: u32,
+
+    // 0 loops forever
+    pub num_plays: u32
+}
+
+// what happens to the frame's region before the next frame is drawn
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DisposeOp {
+    // left as it is
+    None = 0,
+
+    // cleared to transparent black
+    Background = 1,
+
+    // restored to what it was before the frame was drawn
+    Previous = 2
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlendOp {
+    // the frame replaces the region, alpha included
+    Source = 0,
+
+    // the frame is alpha blended over the region
+    Over = 1
+}
+
+// fcTL [src: https://wiki.mozilla.org/APNG_Specification]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameControl {
+    pub sequence_number: u32,
+    pub width: u32,
+    pub height: u32,
+    pub x_offset: u32,
+    pub y_offset: u32,
+    pub delay_num: u16,
+    pub delay_den: u16,
+    pub dispose_op: DisposeOp,
+    pub blend_op: BlendOp
+}
+
+// one frame as stored: its pixels cover only the region given by the frame control
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frame {
+    pub control: FrameControl,
+    pub pixels: ImageBuffer
+}
+
+// how Png::from_frames lays out an animation
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AnimationOptions {
+    // 0 loops forever
+    pub num_plays: u32,
+
+    // store only the region that changed from the previous frame, choosing dispose and blend ops that keep it small
+    pub optimize: bool
+}
+
+impl Default for AnimationOptions {
+    fn default() -> AnimationOptions {
+        AnimationOptions { num_plays: 0, optimize: true }
+    }
+}
+
+// frames read so far, waiting for the image data to be decoded
+#[derive(Clone, Debug, Default)]
+pub(crate) struct PendingFrames {
+    next_sequence: u32,
+
+    // the default image is the first frame when its fcTL comes before IDAT
+    pub(crate) default_image: Option<FrameControl>,
+    pub(crate) frames: Vec<(FrameControl, Vec<u8>)>
+}
+
+impl AnimationControl {
+    pub fn from_actl(data: &[u8]) -> Result<AnimationControl, PngError> {
+        if data.len() != 8 {
+            return Err(PngError::malformed("acTL", "expected 8 bytes"))
+        }
+
+        let num_frames = u32::from_be_bytes(data[0..4].try_into().unwrap());
+        if num_frames == 0 {
+            return Err(PngError::malformed("acTL", "an animation needs at least one frame"))
+        }
+
+        Ok(AnimationControl { num_frames, num_plays: u32::from_be_bytes(data[4..8].try_into().unwrap()) })
+    }
+
+    pub fn to_actl(self) -> Vec<u8> {
+        [self.num_frames.to_be_bytes(), self.num_plays.to_be_bytes()].concat()
+    }
+}
+
+impl FrameControl {
+    // {sequence(4), width(4), height(4), x_offset(4), y_offset(4), delay_num(2), delay_den(2), dispose_op(1), blend_op(1)}
+    pub fn from_fctl(data: &[u8]) -> Result<FrameControl, PngError> {
+        if data.len() != 26 {
+            return Err(PngError::malformed("fcTL", "expected 26 bytes"))
+        }
+
+        let u32_at = |i: usize| u32::from_be_bytes(data[i..i + 4].try_into().unwrap());
+        let u16_at = |i: usize| u16::from_be_bytes(data[i..i + 2].try_into().unwrap());
+
+        let dispose_op = match data[24] {
+            0 => DisposeOp::None,
+            1 => DisposeOp::Background,
+            2 => DisposeOp::Previous,
+            op => return Err(PngError::malformed("fcTL", &format!("invalid dispose_op {}", op)))
+        };
+
+        let blend_op = match data[25] {
+            0 => BlendOp::Source,
+            1 => BlendOp::Over,
+            op => return Err(PngError::malformed("fcTL", &format!("invalid blend_op {}", op)))
+        };
+
+        Ok(FrameControl {
+            sequence_number: u32_at(0),
+            width: u32_at(4),
+            height: u32_at(8),
+            x_offset: u32_at(12),
+            y_offset: u32_at(16),
+            delay_num: u16_at(20),
+            delay_den: u16_at(22),
+            dispose_op,
+            blend_op
+        })
+    }
+
+    pub fn to_fctl(self) -> Vec<u8> {
+        let mut data: Vec<u8> = Vec::with_capacity(26);
+
+        for v in [self.sequence_number, self.width, self.height, self.x_offset, self.y_offset] {
+            data.extend_from_slice(&v.to_be_bytes());
+        }
+        data.extend_from_slice(&self.delay_num.to_be_bytes());
+        data.extend_from_slice(&self.delay_den.to_be_bytes());
+        data.extend_from_slice(&[self.dispose_op as u8, self.blend_op as u8]);
+
+        data
+    }
+
+    // a frame covering the whole canvas, drawn over nothing; sequence numbers are assigned when encoding
+    pub fn full(width: u32, height: u32, delay: Duration) -> FrameControl {
+        let (delay_num, delay_den) = delay_fraction(delay);
+
+        FrameControl { sequence_number: 0, width, height, x_offset: 0, y_offset: 0, delay_num, delay_den, dispose_op: DisposeOp::None, blend_op: BlendOp::Source }
+    }
+
+    // a denominator of 0 means hundredths of a second
+    pub fn delay(&self) -> Duration {
+        let den = if self.delay_den == 0 { 100 } else { self.delay_den };
+
+        Duration::from_secs_f64(self.delay_num as f64 / den as f64)
+    }
+
+    // the region has to be non-empty and fit the canvas
+    pub(crate) fn validate(&self, canvas_width: u32, canvas_height: u32) -> Result<(), PngError> {
+        let fits = |offset: u32, size: u32, canvas: u32| size != 0 && offset.checked_add(size).is_some_and(|end| end <= canvas);
+
+        if !fits(self.x_offset, self.width, canvas_width) || !fits(self.y_offset, self.height, canvas_height) {
+            return Err(PngError::malformed("fcTL", &format!("{}x{} frame at ({}, {}) does not fit the {}x{} canvas", self.width, self.height, self.x_offset, self.y_offset, canvas_width, canvas_height)))
+        }
+
+        Ok(())
+    }
+}
+
+impl PendingFrames {
+    // fcTL and fdAT share one sequence, starting at 0
+    fn check_sequence(&mut self, chunk: &str, sequence_number: u32) -> Result<(), PngError> {
+        if sequence_number != self.next_sequence {
+            return Err(PngError::malformed(chunk, &format!("sequence number {} should be {}", sequence_number, self.next_sequence)))
+        }
+
+        self.next_sequence += 1;
+
+        Ok(())
+    }
+}
+
+impl Png {
+    // an animation from same-sized images, the first of which is also the default image; each image is shown for its delay
+    pub fn from_frames(images: &[ImageBuffer], delays: &[Duration], depth: u8, options: &AnimationOptions) -> Result<Png, PngError> {
+        let first = match images.first() {
+            Some(first) => first,
+            None => return Err(PngError::Encode("an animation needs at least one frame".to_string()))
+        };
+
+        if images.len() != delays.len() {
+            return Err(PngError::Encode(format!("{} frames but {} delays", images.len(), delays.len())))
+        }
+
+        let (width, height, layout) = (first.width(), first.height(), first.layout());
+        if let Some(i) = images.iter().position(|f| (f.width(), f.height(), f.layout()) != (width, height, layout)) {
+            return Err(PngError::Encode(format!("frame {} is {}x{} {:?}, not {}x{} {:?} like the first", i, images[i].width(), images[i].height(), images[i].layout(), width, height, layout)))
+        }
+
+        let mut frames: Vec<Frame> = vec![Frame { control: FrameControl::full(width, height, delays[0]), pixels: first.clone() }];
+
+        // what the canvas holds before the previous frame is drawn, for DisposeOp::Previous
+        let mut before: ImageBuffer = ImageBuffer::new(width, height, ChannelLayout::Rgba);
+
+        for i in 1..images.len() {
+            if !options.optimize {
+                frames.push(Frame { control: FrameControl::full(width, height, delays[i]), pixels: images[i].clone() });
+                continue
+            }
+
+            // each way of disposing the previous frame leaves a different canvas to draw this one over
+            let previous = frames[i - 1].control;
+            let shown = to_rgba(&images[i - 1]);
+
+            let mut cleared = shown.clone();
+            for y in previous.y_offset..previous.y_offset + previous.height {
+                for x in previous.x_offset..previous.x_offset + previous.width {
+                    cleared.put_pixel(x, y, Color16::default());
+                }
+            }
+
+            let mut candidates = vec![(DisposeOp::None, shown), (DisposeOp::Background, cleared)];
+
+            // PREVIOUS on the first frame would be read as BACKGROUND
+            if i > 1 {
+                candidates.push((DisposeOp::Previous, before));
+            }
+
+            let area = |region: Option<(u32, u32, u32, u32)>| region.map_or(0, |(_, _, w, h)| w as u64 * h as u64);
+            let (dispose_op, canvas) = candidates.into_iter().min_by_key(|(_, canvas)| area(changed_region(canvas, &images[i]))).unwrap();
+
+            frames[i - 1].control.dispose_op = dispose_op;
+            frames.push(changed_frame(&canvas, &images[i], delays[i]));
+
+            before = canvas;
+        }
+
+        let mut out = Png::from_image_buffer(first.clone(), depth);
+        out.animation = Some(AnimationControl { num_frames: frames.len() as u32, num_plays: options.num_plays });
+        out.frames = frames;
+
+        Ok(out)
+    }
+
+    pub(crate) fn read_fctl(&mut self, data: &[u8]) -> Result<(), PngError> {
+        let control = FrameControl::from_fctl(data)?;
+        control.validate(self.width, self.height)?;
+
+        let before_idat = !self.chunks.iter().any(|c| c.name == "IDAT");
+        let pending = &mut self.pending_frames;
+        pending.check_sequence("fcTL", control.sequence_number)?;
+
+        if before_idat {
+            // the default image always covers the whole canvas
+            if pending.default_image.is_some() || (control.width, control.height, control.x_offset, control.y_offset) != (self.width, self.height, 0, 0) {
+                return Err(PngError::malformed("fcTL", "the fcTL before IDAT must be the only one and cover the whole image"))
+            }
+
+            pending.default_image = Some(control);
+        } else {
+            pending.frames.push((control, Vec::new()));
+        }
+
+        Ok(())
+    }
+
+    // {sequence(4), frame data}; the data of a frame's fdAT chunks together is one zlib stream, like IDAT
+    pub(crate) fn read_fdat(&mut self, data: &[u8]) -> Result<(), PngError> {
+        if data.len() < 4 {
+            return Err(PngError::malformed("fdAT", "missing sequence number"))
+        }
+
+        let pending = &mut self.pending_frames;
+        pending.check_sequence("fdAT", u32::from_be_bytes(data[0..4].try_into().unwrap()))?;
+
+        match pending.frames.last_mut() {
+            Some((_, frame_data)) => frame_data.extend_from_slice(&data[4..]),
+            None => return Err(PngError::malformed("fdAT", "fdAT without a preceding fcTL"))
+        }
+
+        Ok(())
+    }
+
+    // a broken acTL, fcTL or fdAT leaves a still image, since the default image is complete on its own; later fcTL and fdAT chunks are ignored
+    pub(crate) fn drop_animation(&mut self, e: PngError) {
+        self.animation = None;
+        self.pending_frames = PendingFrames::default();
+        self.frames.clear();
+
+        self.warnings.push(format!("{}; showing the still image", e));
+    }
+
+    // turns the frame controls and data read along with the image into frames, once the default image is decoded
+    pub(crate) fn decode_frames(&mut self) -> Result<(), PngError> {
+        let pending = std::mem::take(&mut self.pending_frames);
+
+        let animation = match self.animation {
+            Some(animation) => animation,
+            None => return Ok(())
+        };
+
+        let mut frames: Vec<Frame> = Vec::new();
+
+        // every frame's pixels count towards the limit along with the default image's
+        let mut budget = self.max_image_bytes().saturating_sub(self.pixels.as_raw().len() * 2);
+        let mut reserve = |width: u32, height: u32| -> Result<usize, PngError> {
+            let bytes = ImageBuffer::<u16>::size_in_bytes(width, height, self.pixels.layout()).filter(|&b| b <= budget);
+            let bytes = bytes.ok_or(PngError::ImageTooLarge { width: self.width, height: self.height, limit: self.max_image_bytes() })?;
+            budget -= bytes;
+
+            Ok(bytes)
+        };
+
+        if let Some(control) = pending.default_image {
+            reserve(control.width, control.height)?;
+            frames.push(Frame { control, pixels: self.pixels.clone() });
+        }
+
+        for (control, data) in pending.frames {
+            if data.is_empty() {
+                return Err(PngError::malformed("fdAT", &format!("frame {} has no image data", control.sequence_number)))
+            }
+
+            reserve(control.width, control.height)?;
+            frames.push(Frame { control, pixels: self.decode_image_data(&data, control.width, control.height)? });
+        }
+
+        if frames.len() != animation.num_frames as usize {
+            return Err(PngError::malformed("acTL", &format!("expected {} frames, found {}", animation.num_frames, frames.len())))
+        }
+
+        self.frames = frames;
+
+        Ok(())
+    }
+
+    // a whole zlib stream of filtered scanlines, decoded with this image's format; frames are interlaced like the default image
+    pub fn decode_image_data(&self, data: &[u8], width: u32, height: u32) -> Result<ImageBuffer, PngError> {
+        let passes: Vec<Pass> = interlace::passes(width, height, self.interface).into_iter().filter(|p| !p.is_empty()).collect();
+
+        // the frame's scanlines bound how far its data is inflated, as with IDAT
+        let expected = passes.iter().map(|p| self.stride(p.width).line_size() * p.height as usize).sum();
+        let inflated = zlib::inflate_zlib_limited(data, expected)?;
+
+        let mut pixels: ImageBuffer = ImageBuffer::try_new(width, height, self.pixels.layout(), self.max_image_bytes())?;
+        let mut offset = 0;
+
+        for pass in passes {
+            let stride = self.stride(pass.width);
+            let line_size = stride.line_size();
+            let mut previous_line: Option<Vec<u8>> = None;
+
+            for y in 0..pass.height {
+                let line = inflated.get(offset..offset + line_size).ok_or(PngError::TruncatedImageData { expected: offset + line_size, found: inflated.len() })?;
+                offset += line_size;
+
+                let mut scanline = Scanline::from_bytes(line, stride.pixel_length as u8, self.depth);
+                scanline.unfilter(previous_line.as_deref())?;
+
+                let row = Row { pass, y, scanline };
+                self.write_row(&mut pixels, None, &row)?;
+
+                previous_line = Some(row.scanline.pixel_bytes);
+            }
+        }
+
+        Ok(pixels)
+    }
+
+    // every frame as it is shown, composited onto an RGBA canvas the size of the image; together they are held to max_image_bytes
+    // [src: https://wiki.mozilla.org/APNG_Specification]
+    pub fn render_frames(&self) -> Result<Vec<ImageBuffer>, PngError> {
+        let too_large = || PngError::ImageTooLarge { width: self.width, height: self.height, limit: self.max_image_bytes() };
+
+        // the working canvas and a saved copy for DisposeOp::Previous, besides the rendered frames
+        let canvas_bytes = ImageBuffer::<u16>::size_in_bytes(self.width, self.height, ChannelLayout::Rgba).ok_or_else(too_large)?;
+        canvas_bytes.checked_mul(self.frames.len() + 2).filter(|&b| b <= self.max_image_bytes()).ok_or_else(too_large)?;
+
+        let mut canvas: ImageBuffer = ImageBuffer::try_new(self.width, self.height, ChannelLayout::Rgba, self.max_image_bytes())?;
+        let mut rendered: Vec<ImageBuffer> = Vec::with_capacity(self.frames.len());
+
+        for (i, frame) in self.frames.iter().enumerate() {
+            let c = frame.control;
+
+            // PREVIOUS on the first frame is treated as BACKGROUND
+            let dispose_op = match (i, c.dispose_op) {
+                (0, DisposeOp::Previous) => DisposeOp::Background,
+                (_, op) => op
+            };
+            let saved = if dispose_op == DisposeOp::Previous { Some(canvas.clone()) } else { None };
+
+            for y in 0..c.height {
+                for x in 0..c.width {
+                    let (cx, cy) = (c.x_offset + x, c.y_offset + y);
+                    let source = frame.pixels.get_pixel(x, y);
+
+                    let color = match c.blend_op {
+                        BlendOp::Source => source,
+                        BlendOp::Over => over(source, canvas.get_pixel(cx, cy))
+                    };
+
+                    canvas.put_pixel(cx, cy, color);
+                }
+            }
+
+            rendered.push(canvas.clone());
+
+            match dispose_op {
+                DisposeOp::None => {},
+                DisposeOp::Background => {
+                    for y in c.y_offset..c.y_offset + c.height {
+                        for x in c.x_offset..c.x_offset + c.width {
+                            canvas.put_pixel(x, y, Color16::default());
+                        }
+                    }
+                },
+                DisposeOp::Previous => canvas = saved.unwrap()
+            }
+        }
+
+        Ok(rendered)
+    }
+}
+
+// frames store only the region to redraw; pixels the region does not change are left transparent for OVER when that is exact
+fn changed_frame(canvas: &ImageBuffer, image: &ImageBuffer, delay: Duration) -> Frame {
+    // an unchanged frame still needs a region, so it redraws one pixel
+    let (x_offset, y_offset, width, height) = changed_region(canvas, image).unwrap_or((0, 0, 1, 1));
+
+    // OVER only reproduces changed pixels that are opaque
+    let over = image.layout().has_alpha() && (y_offset..y_offset + height).all(|y| (x_offset..x_offset + width).all(|x| {
+        let c = image.get_pixel(x, y);
+        c == canvas.get_pixel(x, y) || c.a == 0xFFFF
+    }));
+
+    let mut pixels: ImageBuffer = ImageBuffer::new(width, height, image.layout());
+    for y in 0..height {
+        for x in 0..width {
+            let c = image.get_pixel(x_offset + x, y_offset + y);
+            let unchanged = c == canvas.get_pixel(x_offset + x, y_offset + y);
+
+            pixels.put_pixel(x, y, if over && unchanged { Color16::default() } else { c });
+        }
+    }
+
+    let blend_op = if over { BlendOp::Over } else { BlendOp::Source };
+
+    Frame { control: FrameControl { x_offset, y_offset, width, height, blend_op, ..FrameControl::full(width, height, delay) }, pixels }
+}
+
+// the smallest {x, y, width, height} holding every pixel that differs, or None if the images are the same
+fn changed_region(a: &ImageBuffer, b: &ImageBuffer) -> Option<(u32, u32, u32, u32)> {
+    let mut region: Option<(u32, u32, u32, u32)> = None;
+
+    for y in 0..a.height() {
+        for x in 0..a.width() {
+            if a.get_pixel(x, y) != b.get_pixel(x, y) {
+                region = Some(match region {
+                    Some((x0, y0, x1, y1)) => (x0.min(x), y0.min(y), x1.max(x), y1.max(y)),
+                    None => (x, y, x, y)
+                });
+            }
+        }
+    }
+
+    region.map(|(x0, y0, x1, y1)| (x0, y0, x1 - x0 + 1, y1 - y0 + 1))
+}
+
+fn to_rgba(image: &ImageBuffer) -> ImageBuffer {
+    let mut out: ImageBuffer = ImageBuffer::new(image.width(), image.height(), ChannelLayout::Rgba);
+
+    for y in 0..image.height() {
+        for x in 0..image.width() {
+            out.put_pixel(x, y, image.get_pixel(x, y));
+        }
+    }
+
+    out
+}
+
+// whole milliseconds while they fit, otherwise hundredths of a second
+fn delay_fraction(delay: Duration) -> (u16, u16) {
+    match delay.as_millis() {
+        ms if ms <= u16::MAX as u128 => (ms as u16, 1000),
+        ms => ((ms / 10).min(u16::MAX as u128) as u16, 100)
+    }
+}
+
+// non-premultiplied source over destination
+fn over(source: Color16, destination: Color16) -> Color16 {
+    match (source.a, destination.a) {
+        (0xFFFF, _) | (_, 0) => source,
+        (0, _) => destination,
+        (sa, da) => {
+            let sa = sa as f64 / 65535.0;
+            let da = da as f64 / 65535.0 * (1.0 - sa);
+            let a = sa + da;
+
+            let mix = |s: u16, d: u16| ((s as f64 * sa + d as f64 * da) / a).round() as u16;
+
+            Color16 { r: mix(source.r, destination.r), g: mix(source.g, destination.g), b: mix(source.b, destination.b), a: (a * 65535.0).round() as u16 }
+        }
+    }
+}
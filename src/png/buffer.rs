@@ -0,0 +1,216 @@
+use std::fmt;
+use super::{color::Color16, error::PngError, pixel::Pixel};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum ChannelLayout {
+    Gray,
+    GrayAlpha,
+    Rgb,
+    #[default]
+    Rgba
+}
+
+impl ChannelLayout {
+    pub fn channels(&self) -> usize {
+        match self {
+            ChannelLayout::Gray => 1,
+            ChannelLayout::GrayAlpha => 2,
+            ChannelLayout::Rgb => 3,
+            ChannelLayout::Rgba => 4
+        }
+    }
+
+    pub fn has_alpha(&self) -> bool {
+        matches!(self, ChannelLayout::GrayAlpha | ChannelLayout::Rgba)
+    }
+}
+
+pub trait Sample: Copy + Default + PartialEq + fmt::Debug {
+    const MAX: Self;
+
+    fn as_u16(self) -> u16;
+    fn from_u16(value: u16) -> Self;
+}
+
+impl Sample for u8 {
+    const MAX: u8 = u8::MAX;
+
+    fn as_u16(self) -> u16 { self as u16 * 0x101 }
+    fn from_u16(value: u16) -> u8 { (value >> 8) as u8 }
+}
+
+impl Sample for u16 {
+    const MAX: u16 = u16::MAX;
+
+    fn as_u16(self) -> u16 { self }
+    fn from_u16(value: u16) -> u16 { value }
+}
+
+// interleaved samples stored row by row, e.g. [r, g, b, r, g, b, ...] for Rgb
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ImageBuffer<T: Sample = u16> {
+    width: u32,
+    height: u32,
+    layout: ChannelLayout,
+    data: Vec<T>
+}
+
+impl<T: Sample> ImageBuffer<T> {
+    pub fn new(width: u32, height: u32, layout: ChannelLayout) -> ImageBuffer<T> {
+        ImageBuffer {
+            width,
+            height,
+            layout,
+            data: vec![T::default(); width as usize * height as usize * layout.channels()]
+        }
+    }
+
+    // like new, but the size comes from untrusted input: fails instead of aborting when the samples need more than max_bytes or cannot be allocated
+    pub fn try_new(width: u32, height: u32, layout: ChannelLayout, max_bytes: usize) -> Result<ImageBuffer<T>, PngError> {
+        let too_large = || PngError::ImageTooLarge { width, height, limit: max_bytes };
+
+        let bytes = ImageBuffer::<T>::size_in_bytes(width, height, layout).filter(|&b| b <= max_bytes).ok_or_else(too_large)?;
+        let samples = bytes / std::mem::size_of::<T>();
+
+        let mut data: Vec<T> = Vec::new();
+        data.try_reserve_exact(samples).map_err(|_| too_large())?;
+        data.resize(samples, T::default());
+
+        Ok(ImageBuffer { width, height, layout, data })
+    }
+
+    // memory taken by the samples of an image, or None if that does not fit a usize
+    pub fn size_in_bytes(width: u32, height: u32, layout: ChannelLayout) -> Option<usize> {
+        (width as usize).checked_mul(height as usize)?.checked_mul(layout.channels())?.checked_mul(std::mem::size_of::<T>())
+    }
+
+    pub fn from_raw(width: u32, height: u32, layout: ChannelLayout, data: Vec<T>) -> Option<ImageBuffer<T>> {
+        if data.len() != width as usize * height as usize * layout.channels() {
+            return None
+        }
+
+        Some(ImageBuffer { width, height, layout, data })
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn layout(&self) -> ChannelLayout {
+        self.layout
+    }
+
+    pub fn channels(&self) -> usize {
+        self.layout.channels()
+    }
+
+    pub fn as_raw(&self) -> &[T] {
+        &self.data
+    }
+
+    pub fn into_raw(self) -> Vec<T> {
+        self.data
+    }
+
+    pub fn row(&self, y: u32) -> &[T] {
+        let row_length = self.width as usize * self.channels();
+
+        &self.data[(y as usize * row_length)..((y as usize + 1) * row_length)]
+    }
+
+    pub fn row_mut(&mut self, y: u32) -> &mut [T] {
+        let row_length = self.width as usize * self.channels();
+
+        &mut self.data[(y as usize * row_length)..((y as usize + 1) * row_length)]
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = &[T]> {
+        self.data.chunks(self.width as usize * self.channels())
+    }
+
+    pub fn pixel_samples(&self, x: u32, y: u32) -> &[T] {
+        let i = self.index(x, y);
+
+        &self.data[i..i + self.channels()]
+    }
+
+    pub fn pixel_samples_mut(&mut self, x: u32, y: u32) -> &mut [T] {
+        let i = self.index(x, y);
+        let channels = self.channels();
+
+        &mut self.data[i..i + channels]
+    }
+
+    pub fn get_pixel(&self, x: u32, y: u32) -> Color16 {
+        let s = self.pixel_samples(x, y);
+
+        match self.layout {
+            ChannelLayout::Gray => Color16::grayscale(s[0].as_u16(), 0xFFFF),
+            ChannelLayout::GrayAlpha => Color16::grayscale(s[0].as_u16(), s[1].as_u16()),
+            ChannelLayout::Rgb => Color16 { r: s[0].as_u16(), g: s[1].as_u16(), b: s[2].as_u16(), a: 0xFFFF },
+            ChannelLayout::Rgba => Color16 { r: s[0].as_u16(), g: s[1].as_u16(), b: s[2].as_u16(), a: s[3].as_u16() }
+        }
+    }
+
+    // gray layouts take the red channel of the color
+    pub fn put_pixel(&mut self, x: u32, y: u32, color: Color16) {
+        let layout = self.layout;
+        let s = self.pixel_samples_mut(x, y);
+
+        match layout {
+            ChannelLayout::Gray => { s[0] = T::from_u16(color.r) },
+            ChannelLayout::GrayAlpha => { s[0] = T::from_u16(color.r); s[1] = T::from_u16(color.a) },
+            ChannelLayout::Rgb => { s[0] = T::from_u16(color.r); s[1] = T::from_u16(color.g); s[2] = T::from_u16(color.b) },
+            ChannelLayout::Rgba => { s[0] = T::from_u16(color.r); s[1] = T::from_u16(color.g); s[2] = T::from_u16(color.b); s[3] = T::from_u16(color.a) }
+        }
+    }
+
+    // pixels in row-major order; positions and colors are computed as the iterator advances
+    pub fn pixels(&self) -> impl Iterator<Item = Pixel> + '_ {
+        let width = self.width;
+
+        (0..(self.width as usize * self.height as usize)).map(move |i| {
+            let pos = ((i % width as usize) as u32, (i / width as usize) as u32);
+
+            Pixel {
+                pos,
+                color: self.get_pixel(pos.0, pos.1),
+                palette_index: None
+            }
+        })
+    }
+
+    // nearest-neighbour upscale by an integer factor
+    pub fn scale(&self, scalar: u32, max_bytes: usize) -> Result<ImageBuffer<T>, PngError> {
+        match (self.width.checked_mul(scalar), self.height.checked_mul(scalar)) {
+            (Some(width), Some(height)) => self.resize(width, height, max_bytes),
+            _ => Err(PngError::ImageTooLarge { width: self.width.saturating_mul(scalar), height: self.height.saturating_mul(scalar), limit: max_bytes })
+        }
+    }
+
+    // nearest neighbour, so samples are never blended; the new size is held to max_bytes like try_new
+    pub fn resize(&self, width: u32, height: u32, max_bytes: usize) -> Result<ImageBuffer<T>, PngError> {
+        let mut scaled: ImageBuffer<T> = ImageBuffer::try_new(width, height, self.layout, max_bytes)?;
+
+        for y in 0..scaled.height {
+            let source = self.row((y as u64 * self.height as u64 / height as u64) as u32);
+            let row = scaled.row_mut(y);
+
+            for (x, pixel) in row.chunks_mut(self.layout.channels()).enumerate() {
+                let i = (x as u64 * self.width as u64 / width as u64) as usize * self.layout.channels();
+
+                pixel.copy_from_slice(&source[i..i + self.layout.channels()]);
+            }
+        }
+
+        Ok(scaled)
+    }
+
+    fn index(&self, x: u32, y: u32) -> usize {
+        (x as usize + y as usize * self.width as usize) * self.channels()
+    }
+}
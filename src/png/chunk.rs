@@ -1,4 +1,5 @@
-use std::{convert::TryInto, mem::transmute, str};
+use std::{convert::TryInto, io::{self, Read}, str};
+use super::{error::PngError, helper::crc32};
 
 #[derive(Clone, Debug)]
 pub struct Chunk {
@@ -9,68 +10,159 @@ pub struct Chunk {
 }
 
 impl Chunk {
-    pub fn from_bytes(bytes: &Vec<u8>) -> Vec<Chunk> {
+    // each chunk is {length(4), type(4), data(length), crc(4)} [src: http://libpng.org/pub/png/spec/1.2/PNG-Structure.html#Chunk-layout]
+    pub fn from_bytes(bytes: &[u8]) -> Result<Vec<Chunk>, PngError> {
         let mut chunks: Vec<Chunk> = Vec::new();
-        let chunknames: [&str; 21] = ["IHDR", "PLTE", "IDAT", "IEND", "bKGD", "cHRM", "dSIG", "eXIF", "gAMA", "hIST", "iCCP", "iTXt", "pHYs", "sBIT", "sPLT", "sRGB", "sTER", "tEXT", "tIME", "tRNS", "zTXt"];
 
-        // strip header from bytes
-        let mut chunk_bytes: Vec<u8> = Vec::new();
-        chunk_bytes.extend_from_slice(&bytes[8..bytes.len()]);
+        // skip the 8 byte file header
+        let mut offset: usize = 8;
 
-        let mut chunk_offsets: Vec<u32> = Vec::new();
+        while offset < bytes.len() {
+            if bytes.len() - offset < 12 {
+                return Err(PngError::TruncatedChunk { offset })
+            }
+
+            let (length, name) = Chunk::parse_header(bytes[offset..offset + 8].try_into().unwrap(), offset)?;
+
+            let data_start = offset + 8;
+            let data_end = data_start + length as usize;
+
+            if data_end + 4 > bytes.len() {
+                return Err(PngError::TruncatedChunk { offset })
+            }
+
+            let data: Vec<u8> = bytes[data_start..data_end].to_vec();
+            let crc = u32::from_be_bytes(bytes[data_end..data_end + 4].try_into().unwrap());
 
-        // find the chunk name offset
-        for i in 0..chunk_bytes.len() {
-            // IEND has to be the last chunk; break after marking beginning
-            if &chunk_bytes[i..i+4] == "IEND".as_bytes() {
-                chunk_offsets.push(i as u32);
+            // the crc covers the chunk type and data, but not the length
+            let computed_crc = crc32(&bytes[offset + 4..data_end]);
+            if crc != computed_crc {
+                return Err(PngError::CrcMismatch { chunk: name, stored: crc, computed: computed_crc })
+            }
+
+            offset = data_end + 4;
+
+            let is_end = name == "IEND";
+            chunks.push(Chunk {
+                length,
+                name,
+                data,
+                crc
+            });
 
+            // IEND has to be the last chunk; ignore anything after it
+            if is_end {
                 break
             }
+        }
+
+        Ok(chunks)
+    }
 
-            let s = str::from_utf8(&chunk_bytes[i..i+4]);
-            match s {
-                Ok(val) => {
-                    if chunknames.contains(&val) {
-                        chunk_offsets.push(i as u32);
-                    }
-                },
-                Err(_) => {}
+    // reads the {length(4), type(4)} that starts a chunk; None if the stream ends before it
+    pub fn read_header<R: Read>(reader: &mut R, offset: usize) -> Result<Option<(u32, String)>, PngError> {
+        let mut header = [0u8; 8];
+        let mut filled = 0;
+
+        while filled < header.len() {
+            match reader.read(&mut header[filled..]) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {},
+                Err(e) => return Err(PngError::Io(e))
             }
         }
 
-        for &o in &chunk_offsets {
-            let i = &chunk_offsets.clone().into_iter().position(|x| x == o).unwrap();
+        match filled {
+            0 => Ok(None),
+            8 => Chunk::parse_header(&header, offset).map(Some),
+            _ => Err(PngError::TruncatedChunk { offset })
+        }
+    }
 
-            let length: u32 = unsafe { 
-                transmute::<[u8; 4], u32>(chunk_bytes[(o as usize - 4)..(o as usize)].try_into().unwrap()).to_be() 
-            };
+    // reads the data and crc that follow a chunk header
+    pub fn read_body<R: Read>(reader: &mut R, length: u32, name: String, offset: usize) -> Result<Chunk, PngError> {
+        // read through take() so a bogus length can't allocate ahead of the data
+        let mut data: Vec<u8> = Vec::new();
+        reader.by_ref().take(length as u64).read_to_end(&mut data)?;
 
-            let name: String = str::from_utf8(&chunk_bytes[(o as usize)..(o as usize + 4)]).unwrap().to_owned();
+        if data.len() != length as usize {
+            return Err(PngError::TruncatedChunk { offset })
+        }
 
-            let mut crc_offset: u32 = (chunk_bytes.len() - 4) as u32;
-            if i != &(chunk_offsets.len() - 1) {
-                crc_offset = chunk_offsets[i+1]
-            }
+        let mut crc_bytes = [0u8; 4];
+        Chunk::read_exact(reader, &mut crc_bytes, offset)?;
+        let crc = u32::from_be_bytes(crc_bytes);
 
-            let crc: u32 = unsafe {
-                transmute::<[u8; 4], u32>(chunk_bytes[(crc_offset as usize)..(crc_offset as usize + 4)].try_into().unwrap()).to_be() 
-            };
+        let chunk = Chunk::new(&name, data);
+        if crc != chunk.crc {
+            return Err(PngError::CrcMismatch { chunk: name, stored: crc, computed: chunk.crc })
+        }
 
+        Ok(chunk)
+    }
 
-            let mut data: Vec<u8> = chunk_bytes[(o as usize + 4)..(crc_offset as usize)].into_iter().map(|x| *x).collect();
-            if i != &(chunk_offsets.len() - 1) {
-                data = chunk_bytes[(o as usize + 4)..(crc_offset as usize - 8)].into_iter().map(|x| *x).collect();
-            }
+    // a stream ending inside a chunk is reported as a truncated chunk rather than an I/O error
+    pub fn read_exact<R: Read>(reader: &mut R, buffer: &mut [u8], offset: usize) -> Result<(), PngError> {
+        reader.read_exact(buffer).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => PngError::TruncatedChunk { offset },
+            _ => PngError::Io(e)
+        })
+    }
 
-            chunks.push(Chunk {
-                length: length,
-                name: name,
-                crc: crc,
-                data: data
-            });
+    fn parse_header(header: &[u8; 8], offset: usize) -> Result<(u32, String), PngError> {
+        let length = u32::from_be_bytes(header[0..4].try_into().unwrap());
+
+        // lengths are limited to 2^31 - 1 [src: http://libpng.org/pub/png/spec/1.2/PNG-Structure.html#Chunk-layout]
+        if length > i32::MAX as u32 {
+            return Err(PngError::InvalidChunkLength { offset, length })
         }
 
-        chunks
+        let name_bytes = &header[4..8];
+        if !Chunk::is_valid_name(name_bytes) {
+            return Err(PngError::InvalidChunkType { offset, name: name_bytes.try_into().unwrap() })
+        }
+
+        Ok((length, str::from_utf8(name_bytes).unwrap().to_owned()))
+    }
+
+    pub fn new(name: &str, data: Vec<u8>) -> Chunk {
+        let mut crc_bytes: Vec<u8> = name.as_bytes().to_vec();
+        crc_bytes.extend_from_slice(&data);
+
+        Chunk {
+            length: data.len() as u32,
+            name: name.to_owned(),
+            crc: crc32(&crc_bytes),
+            data
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes: Vec<u8> = Vec::with_capacity(self.data.len() + 12);
+
+        bytes.extend_from_slice(&self.length.to_be_bytes());
+        bytes.extend_from_slice(self.name.as_bytes());
+        bytes.extend_from_slice(&self.data);
+        bytes.extend_from_slice(&self.crc.to_be_bytes());
+
+        bytes
+    }
+
+    // chunk types are four ASCII letters [src: http://libpng.org/pub/png/spec/1.2/PNG-Structure.html#Chunk-naming-conventions]
+    pub fn is_valid_name(name: &[u8]) -> bool {
+        name.len() == 4 && name.iter().all(|b| b.is_ascii_alphabetic())
+    }
+
+    pub fn is_critical(&self) -> bool {
+        self.name.as_bytes()[0].is_ascii_uppercase()
     }
-}
\ No newline at end of file
+
+    pub fn is_private(&self) -> bool {
+        self.name.as_bytes()[1].is_ascii_lowercase()
+    }
+
+    pub fn is_safe_to_copy(&self) -> bool {
+        self.name.as_bytes()[3].is_ascii_lowercase()
+    }
+}
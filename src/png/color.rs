@@ -33,13 +33,18 @@ impl Color {
     }
 
     pub fn as_rgb(&self) -> Color {
+        self.over(&Color::grayscale(255, 255))
+    }
+
+    // blended onto an opaque background
+    pub fn over(&self, background: &Color) -> Color {
         if self.a == 255 {
             *self
         } else {
             let mut c = self.clone().to_owned();
             let f = c.a as f32 / 255.0;
 
-            c.lerp(&Color::grayscale(255, 255), 1.0 - f);
+            c.lerp(background, 1.0 - f);
 
             c.a = 255;
 
@@ -61,15 +66,62 @@ impl Color {
     }
 }
 
+// decoded pixel color, keeping the full precision of 16-bit images (lower bit depths are scaled up to 0-65535)
+#[derive(Clone, PartialEq, Copy, Default)]
+pub struct Color16 {
+    pub r: u16,
+    pub g: u16,
+    pub b: u16,
+    pub a: u16
+}
+
+impl Color16 {
+    pub fn as_hex(&self) -> String {
+        format!("#{:04x?}{:04x?}{:04x?} {:04x?}", self.r, self.g, self.b, self.a)
+    }
+
+    pub fn grayscale(x: u16, a: u16) -> Color16 {
+        Color16 {
+            r: x,
+            g: x,
+            b: x,
+            a
+        }
+    }
+
+    // 0xFF * 0x101 = 0xFFFF, so every 8-bit value maps back onto itself through as_color
+    pub fn from_8bit(c: &Color) -> Color16 {
+        Color16 {
+            r: c.r as u16 * 0x101,
+            g: c.g as u16 * 0x101,
+            b: c.b as u16 * 0x101,
+            a: c.a as u16 * 0x101,
+        }
+    }
+
+    pub fn as_color(&self) -> Color {
+        Color::from_16bit(self.r, self.g, self.b, self.a)
+    }
+}
+
+impl fmt::Debug for Color16 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Color16")
+         .field("hex", &format!("#{:04x?}{:04x?}{:04x?}", self.r, self.g, self.b))
+         .field("alpha", &format!("{:04x?}", self.a))
+         .finish()
+    }
+}
+
 impl ops::Add<Color> for Color {
     type Output = Color;
 
     fn add(self, rhs: Color) -> Self::Output {
         Color { 
-            r: ((self.r + rhs.r as u8) as usize % 256) as u8, 
-            g: ((self.g + rhs.g as u8) as usize % 256) as u8, 
-            b: ((self.b + rhs.b as u8) as usize % 256) as u8, 
-            a: ((self.a + rhs.a as u8) as usize % 256) as u8 
+            r: ((self.r + rhs.r) as usize % 256) as u8, 
+            g: ((self.g + rhs.g) as usize % 256) as u8, 
+            b: ((self.b + rhs.b) as usize % 256) as u8, 
+            a: ((self.a + rhs.a) as usize % 256) as u8 
         }
     }
 }
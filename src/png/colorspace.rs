@@ -0,0 +1,361 @@
+use std::convert::TryInto;
+use super::{error::PngError, gamma, zlib::{self, Inflater}};
+
+pub type Matrix3 = [[f64; 3]; 3];
+
+// a larger iCCP profile is taken to be a zlib bomb rather than a real profile
+pub const MAX_PROFILE_BYTES: usize = 16 << 20;
+
+// white point and primaries as CIE xy, as stored in cHRM [src: http://libpng.org/pub/png/spec/1.2/PNG-Chunks.html#C.cHRM]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Chromaticities {
+    pub white: (f64, f64),
+    pub red: (f64, f64),
+    pub green: (f64, f64),
+    pub blue: (f64, f64)
+}
+
+// [src: http://libpng.org/pub/png/spec/1.2/PNG-Chunks.html#C.sRGB]
+pub const SRGB: Chromaticities = Chromaticities {
+    white: (0.3127, 0.3290),
+    red: (0.64, 0.33),
+    green: (0.30, 0.60),
+    blue: (0.15, 0.06)
+};
+
+// the ICC profile connection space is relative to D50 [src: https://www.color.org/specification/ICC.1-2022-05.pdf]
+pub const D50: [f64; 3] = [0.9642, 1.0, 0.8249];
+
+impl Chromaticities {
+    // {white x, white y, red x, red y, green x, green y, blue x, blue y}, each times 100000
+    pub fn from_chrm(data: &[u8]) -> Result<Chromaticities, PngError> {
+        if data.len() != 32 {
+            return Err(PngError::malformed("cHRM", "expected 32 bytes"))
+        }
+
+        let v: Vec<f64> = data.chunks(4).map(|c| u32::from_be_bytes(c.try_into().unwrap()) as f64 / gamma::GAMA_SCALE).collect();
+
+        Ok(Chromaticities { white: (v[0], v[1]), red: (v[2], v[3]), green: (v[4], v[5]), blue: (v[6], v[7]) })
+    }
+
+    pub fn to_chrm(self) -> Vec<u8> {
+        [self.white, self.red, self.green, self.blue].iter()
+            .flat_map(|&(x, y)| vec![x, y])
+            .flat_map(|v| ((v * gamma::GAMA_SCALE).round() as u32).to_be_bytes().to_vec())
+            .collect()
+    }
+
+    pub fn white_xyz(&self) -> [f64; 3] {
+        xy_to_xyz(self.white)
+    }
+
+    // linear RGB to XYZ, scaled so that white has Y = 1 [src: http://www.brucelindbloom.com/index.html?Eqn_RGB_XYZ_Matrix.html]
+    pub fn rgb_to_xyz(&self) -> Option<Matrix3> {
+        let [r, g, b] = [xy_to_xyz(self.red), xy_to_xyz(self.green), xy_to_xyz(self.blue)];
+        let primaries = [[r[0], g[0], b[0]], [r[1], g[1], b[1]], [r[2], g[2], b[2]]];
+
+        let s = multiply_vector(&invert(&primaries)?, self.white_xyz());
+
+        Some([
+            [primaries[0][0] * s[0], primaries[0][1] * s[1], primaries[0][2] * s[2]],
+            [primaries[1][0] * s[0], primaries[1][1] * s[1], primaries[1][2] * s[2]],
+            [primaries[2][0] * s[0], primaries[2][1] * s[1], primaries[2][2] * s[2]]
+        ])
+    }
+}
+
+fn xy_to_xyz((x, y): (f64, f64)) -> [f64; 3] {
+    if y == 0.0 {
+        return [0.0, 0.0, 0.0]
+    }
+
+    [x / y, 1.0, (1.0 - x - y) / y]
+}
+
+// maps colors seen under one white point to the same appearance under another [src: http://www.brucelindbloom.com/index.html?Eqn_ChromAdapt.html]
+pub fn bradford(from_white: [f64; 3], to_white: [f64; 3]) -> Matrix3 {
+    const BRADFORD: Matrix3 = [
+        [0.8951, 0.2664, -0.1614],
+        [-0.7502, 1.7135, 0.0367],
+        [0.0389, -0.0685, 1.0296]
+    ];
+
+    let from = multiply_vector(&BRADFORD, from_white);
+    let to = multiply_vector(&BRADFORD, to_white);
+    let scale = [[to[0] / from[0], 0.0, 0.0], [0.0, to[1] / from[1], 0.0], [0.0, 0.0, to[2] / from[2]]];
+
+    multiply(&invert(&BRADFORD).unwrap(), &multiply(&scale, &BRADFORD))
+}
+
+pub fn multiply(a: &Matrix3, b: &Matrix3) -> Matrix3 {
+    let mut out = [[0.0; 3]; 3];
+
+    for i in 0..3 {
+        for j in 0..3 {
+            out[i][j] = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+
+    out
+}
+
+pub fn multiply_vector(m: &Matrix3, v: [f64; 3]) -> [f64; 3] {
+    [
+        m[0][0] * v[0] + m[0][1] * v[1] + m[0][2] * v[2],
+        m[1][0] * v[0] + m[1][1] * v[1] + m[1][2] * v[2],
+        m[2][0] * v[0] + m[2][1] * v[1] + m[2][2] * v[2]
+    ]
+}
+
+pub fn invert(m: &Matrix3) -> Option<Matrix3> {
+    let det = m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0]);
+
+    if det.abs() < 1e-12 {
+        return None
+    }
+
+    let cofactor = |r0: usize, r1: usize, c0: usize, c1: usize| m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0];
+
+    Some([
+        [cofactor(1, 2, 1, 2) / det, -cofactor(0, 2, 1, 2) / det, cofactor(0, 1, 1, 2) / det],
+        [-cofactor(1, 2, 0, 2) / det, cofactor(0, 2, 0, 2) / det, -cofactor(0, 1, 0, 2) / det],
+        [cofactor(1, 2, 0, 1) / det, -cofactor(0, 2, 0, 1) / det, cofactor(0, 1, 0, 1) / det]
+    ])
+}
+
+// linear sRGB from XYZ with a D65 white
+pub fn xyz_to_srgb() -> Matrix3 {
+    invert(&SRGB.rgb_to_xyz().unwrap()).unwrap()
+}
+
+// an ICC tone reproduction curve, mapping encoded values in 0.0-1.0 to linear light [src: https://www.color.org/specification/ICC.1-2022-05.pdf]
+#[derive(Clone, Debug, PartialEq)]
+pub enum Curve {
+    Gamma(f64),
+    Table(Vec<u16>),
+    // parametric function type and its parameters g, a, b, c, d, e, f
+    Parametric(u16, Vec<f64>)
+}
+
+impl Curve {
+    pub fn eval(&self, x: f64) -> f64 {
+        match self {
+            Curve::Gamma(g) => x.powf(*g),
+            Curve::Table(table) => {
+                let position = x.clamp(0.0, 1.0) * (table.len() - 1) as f64;
+                let i = (position.floor() as usize).min(table.len() - 2);
+                let t = position - i as f64;
+
+                (table[i] as f64 * (1.0 - t) + table[i + 1] as f64 * t) / u16::MAX as f64
+            },
+            Curve::Parametric(function, p) => {
+                let (g, a, b, c, d, e, f) = (p[0], p[1], p[2], p[3], p[4], p[5], p[6]);
+
+                match function {
+                    0 => x.powf(g),
+                    1 => if x >= -b / a { (a * x + b).powf(g) } else { 0.0 },
+                    2 => if x >= -b / a { (a * x + b).powf(g) + c } else { c },
+                    3 => if x >= d { (a * x + b).powf(g) } else { c * x },
+                    _ => if x >= d { (a * x + b).powf(g) + e } else { c * x + f }
+                }
+            }
+        }
+    }
+}
+
+// the parts of a matrix/TRC profile needed to convert to sRGB [src: https://www.color.org/specification/ICC.1-2022-05.pdf]
+#[derive(Clone, Debug, PartialEq)]
+pub enum MatrixTrc {
+    // colorants are the XYZ (D50) columns for linear red, green and blue
+    Rgb { colorants: Matrix3, curves: [Curve; 3] },
+    Gray { curve: Curve }
+}
+
+// an embedded profile from iCCP; the raw bytes are kept for callers that do their own color management
+#[derive(Clone, Debug, PartialEq)]
+pub struct IccProfile {
+    pub name: String,
+    pub bytes: Vec<u8>
+}
+
+impl IccProfile {
+    // {name(1-79), null(1), compression method(1), zlib stream} [src: http://libpng.org/pub/png/spec/1.2/PNG-Chunks.html#C.iCCP]
+    pub fn from_iccp(data: &[u8]) -> Result<IccProfile, PngError> {
+        let null = match data.iter().position(|&b| b == 0) {
+            Some(i) if (1..=79).contains(&i) => i,
+            _ => return Err(PngError::malformed("iCCP", "profile name must be 1-79 bytes followed by a null"))
+        };
+
+        if data.get(null + 1) != Some(&0) {
+            return Err(PngError::malformed("iCCP", "unknown compression method"))
+        }
+
+        // the profile starts with its size, which bounds how far the stream is inflated
+        let stream = &data[null + 2..];
+        let mut header = Inflater::with_output_limit(4);
+        header.push(stream).map_err(|e| PngError::malformed("iCCP", &e.to_string()))?;
+
+        let size = match header.take_output().get(0..4) {
+            Some(size) => u32::from_be_bytes(size.try_into().unwrap()) as usize,
+            None => return Err(PngError::malformed("iCCP", "profile is too short"))
+        };
+        if size > MAX_PROFILE_BYTES {
+            return Err(PngError::malformed("iCCP", &format!("a {} byte profile is larger than {} bytes", size, MAX_PROFILE_BYTES)))
+        }
+
+        let bytes = zlib::inflate_zlib_limited(stream, size).map_err(|e| PngError::malformed("iCCP", &e.to_string()))?;
+
+        Ok(IccProfile { name: data[..null].iter().map(|&b| b as char).collect(), bytes })
+    }
+
+    pub fn to_iccp(&self) -> Vec<u8> {
+        let mut data: Vec<u8> = self.name.chars().map(|c| c as u8).collect();
+        data.extend_from_slice(&[0, 0]);
+        data.extend(deflate::deflate_bytes_zlib(&self.bytes));
+
+        data
+    }
+
+    // None for profiles this crate can't apply, e.g. LUT-based ones
+    pub fn matrix_trc(&self) -> Option<MatrixTrc> {
+        let bytes = &self.bytes;
+
+        // header(128), tag count(4) [src: https://www.color.org/specification/ICC.1-2022-05.pdf]
+        if bytes.len() < 132 || &bytes[36..40] != b"acsp" {
+            return None
+        }
+
+        // a tag table that claims more entries than the profile holds is forged
+        let count = read_u32(bytes, 128)? as usize;
+        if count.checked_mul(12).and_then(|t| t.checked_add(132)).is_none_or(|end| end > bytes.len()) {
+            return None
+        }
+
+        let tags = |signature: &[u8]| -> Option<&[u8]> {
+            (0..count).map(|i| 132 + i * 12).find(|&entry| bytes.get(entry..entry + 4) == Some(signature)).and_then(|entry| {
+                let offset = read_u32(bytes, entry + 4)? as usize;
+                let size = read_u32(bytes, entry + 8)? as usize;
+
+                bytes.get(offset..offset.checked_add(size)?)
+            })
+        };
+
+        match &bytes[16..20] {
+            b"RGB " => {
+                let r = read_xyz(tags(b"rXYZ")?)?;
+                let g = read_xyz(tags(b"gXYZ")?)?;
+                let b = read_xyz(tags(b"bXYZ")?)?;
+
+                Some(MatrixTrc::Rgb {
+                    colorants: [[r[0], g[0], b[0]], [r[1], g[1], b[1]], [r[2], g[2], b[2]]],
+                    curves: [read_curve(tags(b"rTRC")?)?, read_curve(tags(b"gTRC")?)?, read_curve(tags(b"bTRC")?)?]
+                })
+            },
+            b"GRAY" => Some(MatrixTrc::Gray { curve: read_curve(tags(b"kTRC")?)? }),
+            _ => None
+        }
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(bytes.get(offset..offset + 4)?.try_into().unwrap()))
+}
+
+fn read_s15_fixed16(bytes: &[u8], offset: usize) -> Option<f64> {
+    Some(read_u32(bytes, offset)? as i32 as f64 / 65536.0)
+}
+
+// {'XYZ '(4), reserved(4), X, Y, Z as s15Fixed16}
+fn read_xyz(tag: &[u8]) -> Option<[f64; 3]> {
+    if tag.get(0..4)? != b"XYZ " {
+        return None
+    }
+
+    Some([read_s15_fixed16(tag, 8)?, read_s15_fixed16(tag, 12)?, read_s15_fixed16(tag, 16)?])
+}
+
+fn read_curve(tag: &[u8]) -> Option<Curve> {
+    match tag.get(0..4)? {
+        // {'curv'(4), reserved(4), count(4), entries(2 * count)}; one entry is a u8Fixed8 gamma
+        b"curv" => {
+            let count = read_u32(tag, 8)? as usize;
+
+            match count {
+                0 => Some(Curve::Gamma(1.0)),
+                1 => Some(Curve::Gamma(u16::from_be_bytes(tag.get(12..14)?.try_into().unwrap()) as f64 / 256.0)),
+                _ => Some(Curve::Table(tag.get(12..12 + count * 2)?.chunks(2).map(|c| u16::from_be_bytes(c.try_into().unwrap())).collect()))
+            }
+        },
+        // {'para'(4), reserved(4), function type(2), reserved(2), parameters as s15Fixed16}
+        b"para" => {
+            let function = u16::from_be_bytes(tag.get(8..10)?.try_into().unwrap());
+            let count = match function {
+                0 => 1,
+                1 => 3,
+                2 => 4,
+                3 => 5,
+                4 => 7,
+                _ => return None
+            };
+
+            let mut parameters: Vec<f64> = (0..count).map(|i| read_s15_fixed16(tag, 12 + i * 4)).collect::<Option<Vec<f64>>>()?;
+            parameters.resize(7, 0.0);
+
+            Some(Curve::Parametric(function, parameters))
+        },
+        _ => None
+    }
+}
+
+// samples to linear sRGB: each channel through its curve, then the matrix from the source primaries
+#[derive(Clone, Debug, PartialEq)]
+pub struct ColorTransform {
+    pub curves: [Curve; 3],
+    pub matrix: Option<Matrix3>
+}
+
+impl ColorTransform {
+    // chromaticities from cHRM with a gAMA power law
+    pub fn from_chromaticities(chromaticities: &Chromaticities, file_gamma: f64) -> Option<ColorTransform> {
+        let to_xyz = chromaticities.rgb_to_xyz()?;
+        let adapted = multiply(&bradford(chromaticities.white_xyz(), SRGB.white_xyz()), &to_xyz);
+
+        Some(ColorTransform {
+            curves: [Curve::Gamma(1.0 / file_gamma), Curve::Gamma(1.0 / file_gamma), Curve::Gamma(1.0 / file_gamma)],
+            matrix: Some(multiply(&xyz_to_srgb(), &adapted))
+        })
+    }
+
+    pub fn from_matrix_trc(profile: MatrixTrc) -> ColorTransform {
+        match profile {
+            MatrixTrc::Rgb { colorants, curves } => {
+                let adapted = multiply(&bradford(D50, SRGB.white_xyz()), &colorants);
+
+                ColorTransform { curves, matrix: Some(multiply(&xyz_to_srgb(), &adapted)) }
+            },
+            MatrixTrc::Gray { curve } => ColorTransform { curves: [curve.clone(), curve.clone(), curve], matrix: None }
+        }
+    }
+
+    // true if the matrix leaves colors as they are, e.g. for cHRM with the sRGB primaries
+    pub fn is_identity_matrix(&self) -> bool {
+        match &self.matrix {
+            Some(m) => (0..3).all(|i| (0..3).all(|j| (m[i][j] - if i == j { 1.0 } else { 0.0 }).abs() < 1e-3)),
+            None => true
+        }
+    }
+
+    // components in 0.0-1.0; out-of-gamut results are clipped
+    pub fn to_linear_srgb(&self, rgb: [f64; 3]) -> [f64; 3] {
+        let linear = [self.curves[0].eval(rgb[0]), self.curves[1].eval(rgb[1]), self.curves[2].eval(rgb[2])];
+
+        let converted = match &self.matrix {
+            Some(m) => multiply_vector(m, linear),
+            None => linear
+        };
+
+        [converted[0].clamp(0.0, 1.0), converted[1].clamp(0.0, 1.0), converted[2].clamp(0.0, 1.0)]
+    }
+}
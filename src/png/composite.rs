@@ -0,0 +1,116 @@
+use std::convert::TryInto;
+use super::{
+    buffer::{ChannelLayout, ImageBuffer},
+    color::Color16,
+    display::DisplayOptions,
+    error::PngError,
+    png::Png
+};
+
+// bKGD as stored, at the image bit depth [src: http://libpng.org/pub/png/spec/1.2/PNG-Chunks.html#C.bKGD]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Background {
+    Gray(u16),
+    Rgb(u16, u16, u16),
+    PaletteIndex(u8)
+}
+
+impl Background {
+    // gray and RGB samples have to fit the image bit depth; palette indices are checked against PLTE by the caller
+    pub fn from_bkgd(color_type: u8, depth: u8, data: &[u8]) -> Result<Background, PngError> {
+        let sample = |i: usize| u16::from_be_bytes(data[i..i + 2].try_into().unwrap());
+
+        let background = match (color_type, data.len()) {
+            (0, 2) | (4, 2) => Background::Gray(sample(0)),
+            (2, 6) | (6, 6) => Background::Rgb(sample(0), sample(2), sample(4)),
+            (3, 1) => Background::PaletteIndex(data[0]),
+            _ => return Err(PngError::malformed("bKGD", &format!("{} bytes do not match color type {}", data.len(), color_type)))
+        };
+
+        let samples = match background {
+            Background::Gray(g) => vec![g],
+            Background::Rgb(r, g, b) => vec![r, g, b],
+            Background::PaletteIndex(_) => vec![]
+        };
+        if let Some(s) = samples.into_iter().find(|&s| depth < 16 && s >> depth != 0) {
+            return Err(PngError::malformed("bKGD", &format!("sample {} does not fit {} bits", s, depth)))
+        }
+
+        Ok(background)
+    }
+
+    pub fn to_bkgd(self) -> Vec<u8> {
+        match self {
+            Background::Gray(g) => g.to_be_bytes().to_vec(),
+            Background::Rgb(r, g, b) => [r, g, b].iter().flat_map(|s| s.to_be_bytes().to_vec()).collect(),
+            Background::PaletteIndex(i) => vec![i]
+        }
+    }
+}
+
+// what transparent pixels are drawn over
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum BackgroundPolicy {
+    // the file's bKGD, or white if it has none
+    #[default]
+    File,
+
+    // a color in the same space as the decoded samples, so it is gamma corrected along with them
+    Solid(Color16),
+
+    // squares of size pixels, in display colors
+    Checkerboard { size: u32, light: Color16, dark: Color16 }
+}
+
+impl BackgroundPolicy {
+    pub fn checkerboard() -> BackgroundPolicy {
+        BackgroundPolicy::Checkerboard { size: 8, light: Color16::grayscale(0x9999, 0xFFFF), dark: Color16::grayscale(0x6666, 0xFFFF) }
+    }
+}
+
+impl Png {
+    // the bKGD color scaled to 16 bits like the decoded samples
+    pub fn background_color(&self) -> Option<Color16> {
+        match self.background? {
+            Background::Gray(g) => Some(Color16::grayscale(self.expand_sample(g), 0xFFFF)),
+            Background::Rgb(r, g, b) => Some(Color16 { r: self.expand_sample(r), g: self.expand_sample(g), b: self.expand_sample(b), a: 0xFFFF }),
+            Background::PaletteIndex(i) => self.palette_color(i).ok().map(|c| Color16 { a: 0xFFFF, ..Color16::from_8bit(c) })
+        }
+    }
+
+    // opaque display colors, with every pixel blended over the background
+    pub fn composite(&self, options: &DisplayOptions, policy: &BackgroundPolicy) -> ImageBuffer<u8> {
+        let display = self.to_display(options);
+
+        // a solid background goes through the same display conversion as the image
+        let solid = match policy {
+            BackgroundPolicy::File => Some(self.background_color().unwrap_or_else(|| Color16::grayscale(0xFFFF, 0xFFFF))),
+            BackgroundPolicy::Solid(c) => Some(*c),
+            BackgroundPolicy::Checkerboard { .. } => None
+        }.map(|c| self.display_color(c, options));
+
+        let mut out: ImageBuffer<u8> = ImageBuffer::new(display.width(), display.height(), ChannelLayout::Rgb);
+
+        for y in 0..display.height() {
+            for x in 0..display.width() {
+                let background = match (solid, policy) {
+                    (Some(c), _) => c,
+                    (None, &BackgroundPolicy::Checkerboard { size, light, dark }) => if (x / size.max(1) + y / size.max(1)) % 2 == 0 { light } else { dark },
+                    _ => unreachable!()
+                };
+
+                out.put_pixel(x, y, blend(display.get_pixel(x, y), background));
+            }
+        }
+
+        out
+    }
+}
+
+// foreground over an opaque background, rounded rather than truncated [src: http://libpng.org/pub/png/spec/1.2/PNG-Decoders.html#D.Alpha-channel-processing]
+pub fn blend(foreground: Color16, background: Color16) -> Color16 {
+    let a = foreground.a as u32;
+    let mix = |f: u16, b: u16| ((f as u32 * a + b as u32 * (0xFFFF - a) + 0x7FFF) / 0xFFFF) as u16;
+
+    Color16 { r: mix(foreground.r, background.r), g: mix(foreground.g, background.g), b: mix(foreground.b, background.b), a: 0xFFFF }
+}
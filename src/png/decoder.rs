@@ -0,0 +1,365 @@
+use std::{cmp::min, io::Read};
+
+use super::{
+    chunk::Chunk,
+    error::PngError,
+    helper::update_crc32,
+    interlace::{self, Pass},
+    png::{Png, SIGNATURE},
+    scanline::Scanline,
+    zlib::Inflater
+};
+
+#[derive(Clone, Copy, Debug)]
+pub struct DecodeOptions {
+    // make gray and truecolor pixels matching the tRNS color key transparent; the key is kept on the Png either way
+    pub apply_transparency: bool,
+
+    // spread samples with fewer significant bits than their depth (sBIT) over the full range; off by default so library users get the samples as stored; the viewer always rescales
+    pub rescale_significant_bits: bool,
+
+    // rotate and flip the pixels as the eXIf orientation says, then mark the image as upright
+    pub apply_orientation: bool,
+
+    // the most memory the decoded pixels may take, counting every APNG frame; larger images are an error rather than an abort
+    pub max_image_bytes: usize
+}
+
+impl Default for DecodeOptions {
+    fn default() -> DecodeOptions {
+        DecodeOptions {
+            apply_transparency: true,
+            rescale_significant_bits: false,
+            apply_orientation: false,
+            max_image_bytes: DEFAULT_MAX_IMAGE_BYTES
+        }
+    }
+}
+
+// 1 GiB, e.g. a 16-bit RGBA image of about 11500x11500
+pub const DEFAULT_MAX_IMAGE_BYTES: usize = 1 << 30;
+
+// IDAT data is read and inflated in pieces of at most this many bytes
+const READ_SIZE: usize = 8192;
+
+// an unfiltered scanline of one interlace pass
+pub struct Row {
+    pub pass: Pass,
+
+    // row within the pass
+    pub y: u32,
+
+    pub scanline: Scanline
+}
+
+impl Row {
+    // position in the full image of the pixel at x in this row
+    pub fn position(&self, x: u32) -> (u32, u32) {
+        self.pass.position(x, self.y)
+    }
+}
+
+#[derive(Clone, Copy)]
+struct IdatChunk {
+    offset: usize,
+    length: u32,
+    remaining: usize,
+    crc: u32
+}
+
+// reads chunks as they arrive and yields one row at a time; only the current and previous scanline are kept, plus the inflate window
+pub struct PngDecoder<R: Read> {
+    reader: R,
+
+    // bytes read so far, for error offsets
+    offset: usize,
+
+    // header and ancillary chunks read so far; pixels are left empty
+    info: Png,
+
+    inflater: Inflater,
+    inflated: Vec<u8>,
+    found: usize,
+    expected: usize,
+
+    idat: Option<IdatChunk>,
+
+    // the header of the chunk that ended the IDAT sequence
+    next_chunk: Option<(usize, u32, String)>,
+
+    passes: Vec<Pass>,
+    pass_index: usize,
+    row_index: u32,
+    previous_line: Option<Vec<u8>>,
+
+    finished: bool
+}
+
+impl<R: Read> PngDecoder<R> {
+    // reads the signature and every chunk up to the first IDAT
+    pub fn new(mut reader: R) -> Result<PngDecoder<R>, PngError> {
+        let mut signature = [0u8; 8];
+        match Chunk::read_exact(&mut reader, &mut signature, 0) {
+            Err(PngError::TruncatedChunk { .. }) => return Err(PngError::BadSignature),
+            result => result?
+        }
+
+        if signature != SIGNATURE {
+            return Err(PngError::BadSignature)
+        }
+
+        let mut decoder = PngDecoder {
+            reader,
+            offset: signature.len(),
+            info: Png::default(),
+            inflater: Inflater::new(),
+            inflated: Vec::new(),
+            found: 0,
+            expected: 0,
+            idat: None,
+            next_chunk: None,
+            passes: Vec::new(),
+            pass_index: 0,
+            row_index: 0,
+            previous_line: None,
+            finished: false
+        };
+
+        loop {
+            let (offset, length, name) = match decoder.read_header()? {
+                Some(header) => header,
+                None if decoder.info.chunks.is_empty() => return Err(PngError::MissingIhdr),
+                None => return Err(PngError::MissingIdat)
+            };
+
+            // IHDR must come first [src: http://libpng.org/pub/png/spec/1.2/PNG-Chunks.html#C.Summary-of-standard-chunks]
+            if decoder.info.chunks.is_empty() && name != "IHDR" {
+                return Err(PngError::MissingIhdr)
+            }
+
+            match &name[..] {
+                "IDAT" => {
+                    decoder.idat = Some(IdatChunk::new(offset, length));
+                    break
+                },
+                "IEND" => return Err(PngError::MissingIdat),
+                _ => decoder.read_chunk(offset, length, name)?
+            }
+        }
+
+        let info = &decoder.info;
+        if info.color_type == 3 && info.colors.is_empty() {
+            return Err(PngError::MissingPlte)
+        }
+
+        decoder.passes = interlace::passes(info.width, info.height, info.interface).into_iter().filter(|p| !p.is_empty()).collect();
+        decoder.expected = decoder.passes.iter().map(|p| info.stride(p.width).line_size() * p.height as usize).sum();
+
+        // the inflater never holds more than a scanline of output
+        let longest_line = decoder.passes.iter().map(|p| info.stride(p.width).line_size()).max().unwrap_or(1);
+        decoder.inflater = Inflater::with_output_limit(longest_line);
+
+        Ok(decoder)
+    }
+
+    // the chunks read so far; everything after the image data is added once the last row has been read
+    pub fn info(&self) -> &Png {
+        &self.info
+    }
+
+    pub fn bytes_read(&self) -> usize {
+        self.offset
+    }
+
+    pub fn into_info(self) -> Png {
+        self.info
+    }
+
+    // rows come pass by pass, top to bottom; None once the image and its trailing chunks have been read
+    pub fn next_row(&mut self) -> Result<Option<Row>, PngError> {
+        let pass = match self.passes.get(self.pass_index) {
+            Some(&pass) => pass,
+            None => {
+                self.finish()?;
+                return Ok(None)
+            }
+        };
+
+        let stride = self.info.stride(pass.width);
+        let line_size = stride.line_size();
+
+        while self.inflated.len() < line_size {
+            if !self.fill()? {
+                return Err(PngError::TruncatedImageData { expected: self.expected, found: self.found })
+            }
+        }
+
+        let mut scanline = Scanline::from_bytes(&self.inflated[..line_size], stride.pixel_length as u8, self.info.depth);
+        self.inflated.drain(..line_size);
+
+        scanline.unfilter(self.previous_line.as_deref())?;
+
+        let row = Row { pass, y: self.row_index, scanline };
+
+        self.row_index += 1;
+        if self.row_index == pass.height {
+            self.pass_index += 1;
+            self.row_index = 0;
+            self.previous_line = None;
+        } else {
+            self.previous_line = Some(row.scanline.pixel_bytes.clone());
+        }
+
+        Ok(Some(row))
+    }
+
+    // calls f with every row, then returns the chunks read along the way
+    pub fn for_each_row<F: FnMut(&Row) -> Result<(), PngError>>(mut self, mut f: F) -> Result<Png, PngError> {
+        while let Some(row) = self.next_row()? {
+            f(&row)?;
+        }
+
+        Ok(self.info)
+    }
+
+    fn read_header(&mut self) -> Result<Option<(usize, u32, String)>, PngError> {
+        let offset = self.offset;
+        let header = Chunk::read_header(&mut self.reader, offset)?;
+
+        self.offset += 8;
+
+        Ok(header.map(|(length, name)| (offset, length, name)))
+    }
+
+    fn read_chunk(&mut self, offset: usize, length: u32, name: String) -> Result<(), PngError> {
+        let chunk = Chunk::read_body(&mut self.reader, length, name, offset)?;
+        self.offset += length as usize + 4;
+
+        // a broken ancillary chunk is dropped with a warning, since the image is complete without it [src: http://libpng.org/pub/png/spec/1.2/PNG-Decoders.html#D.Error-checking]
+        match self.info.read_chunk(&chunk) {
+            Ok(()) => self.info.chunks.push(chunk),
+            Err(e) if !chunk.is_critical() => self.info.warnings.push(e.to_string()),
+            Err(e) => return Err(e)
+        }
+
+        Ok(())
+    }
+
+    // inflates more image data; false once the IDAT chunks are used up
+    fn fill(&mut self) -> Result<bool, PngError> {
+        // output held back by the inflater's limit comes first
+        self.inflater.push(&[])?;
+        if self.take_inflated() {
+            return Ok(true)
+        }
+
+        loop {
+            let mut idat = match self.idat {
+                Some(idat) => idat,
+                None => return Ok(false)
+            };
+
+            if idat.remaining == 0 {
+                self.end_idat(idat)?;
+
+                // IDAT chunks must be consecutive [src: http://libpng.org/pub/png/spec/1.2/PNG-Chunks.html#C.IDAT]
+                match self.read_header()? {
+                    Some((offset, length, name)) if name == "IDAT" => self.idat = Some(IdatChunk::new(offset, length)),
+                    header => {
+                        self.idat = None;
+                        self.next_chunk = header;
+                    }
+                }
+
+                continue
+            }
+
+            let mut buffer = [0u8; READ_SIZE];
+            let n = min(READ_SIZE, idat.remaining);
+
+            Chunk::read_exact(&mut self.reader, &mut buffer[..n], idat.offset)?;
+            self.offset += n;
+
+            idat.crc = update_crc32(idat.crc, &buffer[..n]);
+            idat.remaining -= n;
+            self.idat = Some(idat);
+
+            self.inflater.push(&buffer[..n])?;
+            if self.take_inflated() {
+                return Ok(true)
+            }
+        }
+    }
+
+    fn take_inflated(&mut self) -> bool {
+        let output = self.inflater.take_output();
+
+        self.found += output.len();
+        self.inflated.extend_from_slice(&output);
+
+        !output.is_empty()
+    }
+
+    // IDAT data goes straight to the inflater, so the recorded chunk keeps its length but no data
+    fn end_idat(&mut self, idat: IdatChunk) -> Result<(), PngError> {
+        let mut crc_bytes = [0u8; 4];
+        Chunk::read_exact(&mut self.reader, &mut crc_bytes, idat.offset)?;
+        self.offset += 4;
+
+        let stored = u32::from_be_bytes(crc_bytes);
+        let computed = idat.crc ^ 0xFFFFFFFF;
+
+        if stored != computed {
+            return Err(PngError::CrcMismatch { chunk: "IDAT".to_owned(), stored, computed })
+        }
+
+        self.info.chunks.push(Chunk { length: idat.length, name: "IDAT".to_owned(), data: Vec::new(), crc: stored });
+
+        Ok(())
+    }
+
+    // checks the rest of the zlib stream and reads the chunks after the image data
+    fn finish(&mut self) -> Result<(), PngError> {
+        if self.finished {
+            return Ok(())
+        }
+
+        // anything inflated past the last row is ignored
+        while self.fill()? {
+            self.inflated.clear();
+        }
+
+        if !self.inflater.is_finished() {
+            return Err(PngError::Inflate("unexpected end of zlib stream".to_owned()))
+        }
+
+        let mut header = self.next_chunk.take();
+        loop {
+            let (offset, length, name) = match header {
+                Some(header) => header,
+                None => return Err(PngError::MissingIend)
+            };
+
+            match &name[..] {
+                "IDAT" => return Err(PngError::NonConsecutiveIdat),
+                "IEND" => {
+                    self.read_chunk(offset, length, name)?;
+                    break
+                },
+                _ => self.read_chunk(offset, length, name)?
+            }
+
+            header = self.read_header()?;
+        }
+
+        self.finished = true;
+
+        Ok(())
+    }
+}
+
+impl IdatChunk {
+    fn new(offset: usize, length: u32) -> IdatChunk {
+        IdatChunk { offset, length, remaining: length as usize, crc: update_crc32(0xFFFFFFFF, b"IDAT") }
+    }
+}
@@ -0,0 +1,128 @@
+use super::{
+    buffer::{ChannelLayout, ImageBuffer},
+    color::Color16,
+    colorspace::ColorTransform,
+    gamma::{self, GammaTable},
+    png::Png
+};
+
+// how decoded samples are turned into colors for the screen; the decoded pixels themselves are never changed
+#[derive(Clone, Copy, Debug)]
+pub struct DisplayOptions {
+    pub display_gamma: f64,
+
+    // false passes samples through exactly as decoded
+    pub gamma_correction: bool,
+
+    // convert from the primaries given by iCCP or cHRM to sRGB
+    pub color_management: bool
+}
+
+impl Default for DisplayOptions {
+    fn default() -> DisplayOptions {
+        DisplayOptions {
+            display_gamma: gamma::SRGB_DISPLAY_GAMMA,
+            gamma_correction: true,
+            color_management: true
+        }
+    }
+}
+
+impl Png {
+    // sRGB overrides gAMA; None if the file gives no gamma [src: http://libpng.org/pub/png/spec/1.2/PNG-Chunks.html#C.sRGB]
+    pub fn file_gamma(&self) -> Option<f64> {
+        if self.chunks.iter().any(|c| c.name == "sRGB") {
+            return Some(gamma::SRGB_FILE_GAMMA)
+        }
+
+        match self.gamma {
+            0 => None,
+            g => Some(g as f64 / gamma::GAMA_SCALE)
+        }
+    }
+
+    // images without gamma information are shown as they are
+    pub fn gamma_table(&self, options: &DisplayOptions) -> GammaTable {
+        match self.file_gamma() {
+            Some(file_gamma) if options.gamma_correction => GammaTable::new(file_gamma, options.display_gamma),
+            _ => GammaTable::identity()
+        }
+    }
+
+    // how samples map to linear sRGB, if the file says more than a gamma: iCCP takes precedence over sRGB, which takes precedence over cHRM [src: http://libpng.org/pub/png/spec/1.2/PNG-Chunks.html#C.iCCP]
+    pub fn color_transform(&self) -> Option<ColorTransform> {
+        if let Some(profile) = self.icc_profile.as_ref().and_then(|p| p.matrix_trc()) {
+            return Some(ColorTransform::from_matrix_trc(profile))
+        }
+
+        if self.chunks.iter().any(|c| c.name == "sRGB") || self.color_type == 0 || self.color_type == 4 {
+            return None
+        }
+
+        // primaries that match sRGB only need the gamma table
+        let file_gamma = self.file_gamma().unwrap_or(gamma::SRGB_FILE_GAMMA);
+        self.chromaticities.as_ref()
+                           .and_then(|c| ColorTransform::from_chromaticities(c, file_gamma))
+                           .filter(|t| !t.is_identity_matrix())
+    }
+
+    // RGBA colors for the screen; alpha is left alone
+    pub fn to_display(&self, options: &DisplayOptions) -> ImageBuffer<u8> {
+        let mut out: ImageBuffer<u8> = ImageBuffer::new(self.pixels.width(), self.pixels.height(), ChannelLayout::Rgba);
+
+        let convert = self.display_converter(options);
+
+        for y in 0..self.pixels.height() {
+            for x in 0..self.pixels.width() {
+                out.put_pixel(x, y, convert(self.pixels.get_pixel(x, y)));
+            }
+        }
+
+        out
+    }
+
+    // a single color in the file's color space, e.g. bKGD, as to_display would show it
+    pub fn display_color(&self, c: Color16, options: &DisplayOptions) -> Color16 {
+        self.display_converter(options)(c)
+    }
+
+    fn display_converter(&self, options: &DisplayOptions) -> Box<dyn Fn(Color16) -> Color16> {
+        let transform = match options.color_management && options.gamma_correction {
+            true => self.color_transform(),
+            false => None
+        };
+
+        match transform {
+            Some(transform) => {
+                let exponent = 1.0 / options.display_gamma;
+                let encode = move |linear: f64| gamma::from_unit(linear.powf(exponent));
+
+                Box::new(move |c: Color16| {
+                    let [r, g, b] = transform.to_linear_srgb([gamma::to_unit(c.r), gamma::to_unit(c.g), gamma::to_unit(c.b)]);
+
+                    Color16 { r: encode(r), g: encode(g), b: encode(b), a: c.a }
+                })
+            },
+            None => {
+                let table = self.gamma_table(options);
+
+                Box::new(move |c: Color16| Color16 { r: table.apply(c.r), g: table.apply(c.g), b: table.apply(c.b), a: c.a })
+            }
+        }
+    }
+
+    // [r, g, b, a] in linear light, for blending and resampling; converted to sRGB primaries when the file gives its own, and files without gamma are taken to be sRGB
+    pub fn linear_color(&self, x: u32, y: u32) -> [f64; 4] {
+        let c = self.pixels.get_pixel(x, y);
+
+        if let Some(transform) = self.color_transform() {
+            let [r, g, b] = transform.to_linear_srgb([gamma::to_unit(c.r), gamma::to_unit(c.g), gamma::to_unit(c.b)]);
+
+            return [r, g, b, gamma::to_unit(c.a)]
+        }
+
+        let file_gamma = self.file_gamma().unwrap_or(gamma::SRGB_FILE_GAMMA);
+
+        [gamma::to_linear(c.r, file_gamma), gamma::to_linear(c.g, file_gamma), gamma::to_linear(c.b, file_gamma), gamma::to_unit(c.a)]
+    }
+}
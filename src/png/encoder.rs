@@ -0,0 +1,287 @@
+use std::{fs::File, io::{BufWriter, Write}};
+use deflate::{deflate_bytes_zlib_conf, Compression};
+
+use super::{
+    apng::{AnimationControl, FrameControl},
+    buffer::ImageBuffer,
+    chunk::Chunk,
+    color::Color16,
+    error::PngError,
+    interlace,
+    png::{Png, SIGNATURE},
+    scanline::{FilterStrategy, Scanline}
+};
+
+#[derive(Clone, Copy, Debug)]
+pub struct EncodeOptions {
+    pub compression: Compression,
+    pub filter: FilterStrategy,
+
+    // carry iCCP, gAMA, sRGB, cHRM and pHYs over from the source image
+    pub ancillary_chunks: bool
+}
+
+impl Default for EncodeOptions {
+    fn default() -> EncodeOptions {
+        EncodeOptions {
+            compression: Compression::Default,
+            filter: FilterStrategy::MinSum,
+            ancillary_chunks: true
+        }
+    }
+}
+
+impl Png {
+    pub fn write_to_file(&self, filepath: &str) -> Result<(), PngError> {
+        let mut writer = BufWriter::new(File::create(filepath)?);
+
+        self.write_to(&mut writer, &EncodeOptions::default())?;
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W, options: &EncodeOptions) -> Result<(), PngError> {
+        writer.write_all(&self.to_bytes(options)?)?;
+
+        Ok(())
+    }
+
+    pub fn to_bytes(&self, options: &EncodeOptions) -> Result<Vec<u8>, PngError> {
+        self.validate_ihdr()?;
+
+        let mut bytes: Vec<u8> = SIGNATURE.to_vec();
+
+        for chunk in self.encode_chunks(options)? {
+            bytes.extend_from_slice(&chunk.to_bytes());
+        }
+
+        Ok(bytes)
+    }
+
+    // encodes with every filter strategy and keeps the smallest output
+    pub fn to_smallest_bytes(&self, options: &EncodeOptions) -> Result<Vec<u8>, PngError> {
+        let strategies = [
+            FilterStrategy::Fixed(0),
+            FilterStrategy::Fixed(1),
+            FilterStrategy::Fixed(2),
+            FilterStrategy::Fixed(3),
+            FilterStrategy::Fixed(4),
+            FilterStrategy::MinSum,
+            FilterStrategy::Entropy,
+            FilterStrategy::BruteForce,
+        ];
+
+        let mut smallest: Option<Vec<u8>> = None;
+
+        for filter in strategies.iter() {
+            let bytes = self.to_bytes(&EncodeOptions { filter: *filter, ..*options })?;
+
+            if smallest.as_ref().is_none_or(|s| bytes.len() < s.len()) {
+                smallest = Some(bytes);
+            }
+        }
+
+        Ok(smallest.unwrap())
+    }
+
+    // chunk order follows [src: http://libpng.org/pub/png/spec/1.2/PNG-Chunks.html#C.Summary-of-standard-chunks]
+    fn encode_chunks(&self, options: &EncodeOptions) -> Result<Vec<Chunk>, PngError> {
+        let mut chunks: Vec<Chunk> = Vec::new();
+
+        let mut ihdr: Vec<u8> = Vec::new();
+        ihdr.extend_from_slice(&self.width.to_be_bytes());
+        ihdr.extend_from_slice(&self.height.to_be_bytes());
+        ihdr.extend_from_slice(&[self.depth, self.color_type, 0, 0, self.interface]);
+        chunks.push(Chunk::new("IHDR", ihdr));
+
+        if options.ancillary_chunks {
+            if let Some(significant_bits) = &self.significant_bits {
+                chunks.push(Chunk::new("sBIT", significant_bits.to_sbit()));
+            }
+            if let Some(profile) = &self.icc_profile {
+                chunks.push(Chunk::new("iCCP", profile.to_iccp()));
+            }
+            if self.gamma != 0 {
+                chunks.push(Chunk::new("gAMA", self.gamma.to_be_bytes().to_vec()));
+            }
+            if self.chunks.iter().any(|c| c.name == "sRGB") {
+                chunks.push(Chunk::new("sRGB", vec![self.rendering_intent]));
+            }
+            if let Some(chromaticities) = &self.chromaticities {
+                chunks.push(Chunk::new("cHRM", chromaticities.to_chrm()));
+            }
+            if self.ppu_x != 0 && self.ppu_y != 0 {
+                let mut phys: Vec<u8> = Vec::new();
+                phys.extend_from_slice(&self.ppu_x.to_be_bytes());
+                phys.extend_from_slice(&self.ppu_y.to_be_bytes());
+                phys.push(self.unit_spec);
+
+                chunks.push(Chunk::new("pHYs", phys));
+            }
+            if let Some(stereo) = self.stereo {
+                chunks.push(Chunk::new("sTER", stereo.to_ster()));
+            }
+            for palette in &self.suggested_palettes {
+                chunks.push(Chunk::new("sPLT", palette.to_splt()));
+            }
+            if let Some(exif) = &self.exif {
+                chunks.push(Chunk::new("eXIf", exif.bytes.clone()));
+            }
+            if let Some(modified) = self.modified {
+                chunks.push(Chunk::new("tIME", modified.to_time()));
+            }
+            for text in &self.text_chunks {
+                chunks.push(Chunk::new(text.chunk_name(), text.to_chunk_data()));
+            }
+        }
+
+        // acTL only has to come before IDAT; the frame count is taken from the frames themselves [src: https://wiki.mozilla.org/APNG_Specification]
+        let animation = self.animation.filter(|_| !self.frames.is_empty());
+        if let Some(animation) = animation {
+            chunks.push(Chunk::new("acTL", AnimationControl { num_frames: self.frames.len() as u32, ..animation }.to_actl()));
+        }
+
+        if self.color_type == 3 {
+            if self.colors.is_empty() || self.colors.len() > 1 << self.depth {
+                return Err(PngError::Encode(format!("{} palette entries do not fit a {}-bit image", self.colors.len(), self.depth)))
+            }
+
+            chunks.push(Chunk::new("PLTE", self.colors.iter().flat_map(|c| vec![c.r, c.g, c.b]).collect()));
+        }
+
+        if let Some(trns) = self.encode_trns() {
+            chunks.push(Chunk::new("tRNS", trns));
+        }
+
+        // bKGD and hIST come after PLTE; a histogram left over from a different palette is dropped
+        if let (true, Some(background)) = (options.ancillary_chunks, self.background) {
+            chunks.push(Chunk::new("bKGD", background.to_bkgd()));
+        }
+        if let (true, 3, Some(histogram)) = (options.ancillary_chunks, self.color_type, self.histogram.as_ref().filter(|h| h.len() == self.colors.len())) {
+            chunks.push(Chunk::new("hIST", histogram.iter().flat_map(|f| f.to_be_bytes().to_vec()).collect()));
+        }
+
+        // fcTL and fdAT share one sequence, renumbered from 0
+        let mut sequence: u32 = 0;
+        let mut frames = if animation.is_some() { &self.frames[..] } else { &[] };
+
+        // the default image is the first frame when that frame is exactly the image
+        if let Some(first) = frames.first().filter(|f| (f.control.x_offset, f.control.y_offset) == (0, 0) && f.pixels == self.pixels) {
+            chunks.push(Chunk::new("fcTL", self.encode_fctl(&first.control, &first.pixels, &mut sequence)?));
+            frames = &frames[1..];
+        }
+
+        let compressed = deflate_bytes_zlib_conf(&self.encode_image_data(&self.pixels, self.indices.as_ref(), options)?, options.compression);
+        chunks.push(Chunk::new("IDAT", compressed));
+
+        for frame in frames {
+            chunks.push(Chunk::new("fcTL", self.encode_fctl(&frame.control, &frame.pixels, &mut sequence)?));
+
+            let mut fdat = sequence.to_be_bytes().to_vec();
+            fdat.extend_from_slice(&deflate_bytes_zlib_conf(&self.encode_image_data(&frame.pixels, None, options)?, options.compression));
+            chunks.push(Chunk::new("fdAT", fdat));
+            sequence += 1;
+        }
+
+        chunks.push(Chunk::new("IEND", Vec::new()));
+
+        Ok(chunks)
+    }
+
+    fn encode_fctl(&self, control: &FrameControl, pixels: &ImageBuffer, sequence: &mut u32) -> Result<Vec<u8>, PngError> {
+        if (control.width, control.height) != (pixels.width(), pixels.height()) {
+            return Err(PngError::Encode(format!("frame {} has {}x{} pixels for a {}x{} region", sequence, pixels.width(), pixels.height(), control.width, control.height)))
+        }
+        control.validate(self.width, self.height).map_err(|e| PngError::Encode(e.to_string()))?;
+
+        let fctl = FrameControl { sequence_number: *sequence, ..*control }.to_fctl();
+        *sequence += 1;
+
+        Ok(fctl)
+    }
+
+    fn encode_trns(&self) -> Option<Vec<u8>> {
+        match self.color_type {
+            0 => self.gray_lvl.map(|g| g.to_be_bytes().to_vec()),
+            2 => self.truecolor_alpha.map(|c| [c.r, c.g, c.b].iter().flat_map(|s| s.to_be_bytes().to_vec()).collect()),
+            3 => {
+                // trailing opaque entries can be left out
+                let count = self.colors.iter().rposition(|c| c.a != 255).map(|i| i + 1)?;
+
+                Some(self.colors[..count].iter().map(|c| c.a).collect())
+            },
+            _ => None
+        }
+    }
+
+    // the inverse of decode_image_data: {filter(1), packed samples} rows for every pass
+    fn encode_image_data(&self, pixels: &ImageBuffer, indices: Option<&ImageBuffer<u8>>, options: &EncodeOptions) -> Result<Vec<u8>, PngError> {
+        let mut data: Vec<u8> = Vec::new();
+
+        for pass in interlace::passes(pixels.width(), pixels.height(), self.interface).iter().filter(|p| !p.is_empty()) {
+            let stride = self.stride(pass.width);
+            let mut previous_line: Option<Vec<u8>> = None;
+
+            for py in 0..pass.height {
+                let mut samples: Vec<u16> = Vec::with_capacity(pass.width as usize * self.channels());
+
+                for px in 0..pass.width {
+                    let (x, y) = pass.position(px, py);
+                    let c = pixels.get_pixel(x, y);
+
+                    match self.color_type {
+                        0 => samples.push(self.reduce_sample(c.r)),
+                        2 => samples.extend_from_slice(&[self.reduce_sample(c.r), self.reduce_sample(c.g), self.reduce_sample(c.b)]),
+                        3 => samples.push(self.palette_index(indices, x, y, &c)?),
+                        4 => samples.extend_from_slice(&[self.reduce_sample(c.r), self.reduce_sample(c.a)]),
+                        _ => samples.extend_from_slice(&[self.reduce_sample(c.r), self.reduce_sample(c.g), self.reduce_sample(c.b), self.reduce_sample(c.a)])
+                    }
+                }
+
+                let pixel_bytes = pack_samples(&samples, self.depth, stride.row_length);
+
+                let mut scanline = Scanline::from_pixel_bytes(pixel_bytes.clone(), stride.pixel_length as u8, self.depth);
+                scanline.apply_filter(options.filter, previous_line.as_deref())?;
+
+                data.extend_from_slice(&scanline.raw_bytes);
+                previous_line = Some(pixel_bytes);
+            }
+        }
+
+        Ok(data)
+    }
+
+    // the stored index if there is one, otherwise the first palette entry with the pixel's color
+    fn palette_index(&self, indices: Option<&ImageBuffer<u8>>, x: u32, y: u32, color: &Color16) -> Result<u16, PngError> {
+        if let Some(indices) = indices {
+            let i = indices.pixel_samples(x, y)[0];
+
+            return self.palette_color(i).map(|_| i as u16)
+        }
+
+        self.colors.iter()
+                   .position(|c| Color16::from_8bit(c) == *color)
+                   .map(|i| i as u16)
+                   .ok_or_else(|| PngError::Encode(format!("color {} is not in the palette", color.as_hex())))
+    }
+}
+
+// samples are packed big-endian, with sub-byte samples filling each byte from the most significant bit
+fn pack_samples(samples: &[u16], depth: u8, row_length: usize) -> Vec<u8> {
+    match depth {
+        16 => samples.iter().flat_map(|s| s.to_be_bytes().to_vec()).collect(),
+        8 => samples.iter().map(|&s| s as u8).collect(),
+        _ => {
+            let mut bytes: Vec<u8> = vec![0; row_length];
+
+            for (i, &s) in samples.iter().enumerate() {
+                let bit = i * depth as usize;
+
+                bytes[bit / 8] |= (s as u8) << (8 - depth as usize - bit % 8);
+            }
+
+            bytes
+        }
+    }
+}
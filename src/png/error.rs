@@ -0,0 +1,75 @@
+use std::{error, fmt, io};
+
+#[derive(Debug)]
+pub enum PngError {
+    Io(io::Error),
+    BadSignature,
+    TruncatedChunk { offset: usize },
+    InvalidChunkType { offset: usize, name: [u8; 4] },
+    InvalidChunkLength { offset: usize, length: u32 },
+    CrcMismatch { chunk: String, stored: u32, computed: u32 },
+    MalformedChunk { chunk: String, reason: String },
+    UnknownCriticalChunk(String),
+    MissingIhdr,
+    InvalidIhdr(String),
+    ImageTooLarge { width: u32, height: u32, limit: usize },
+    UnsupportedColorType(u8),
+    MissingPlte,
+    PaletteIndexOutOfRange { index: u8, entries: usize },
+    MissingIdat,
+    NonConsecutiveIdat,
+    MissingIend,
+    Inflate(String),
+    BadFilterType(u8),
+    TruncatedImageData { expected: usize, found: usize },
+    Encode(String),
+}
+
+impl PngError {
+    pub fn malformed(chunk: &str, reason: &str) -> PngError {
+        PngError::MalformedChunk { chunk: chunk.to_owned(), reason: reason.to_owned() }
+    }
+}
+
+impl fmt::Display for PngError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PngError::Io(e) => write!(f, "I/O error: {}", e),
+            PngError::BadSignature => write!(f, "invalid PNG file signature"),
+            PngError::TruncatedChunk { offset } => write!(f, "truncated chunk at offset {}", offset),
+            PngError::InvalidChunkType { offset, name } => write!(f, "invalid chunk type {:02x?} at offset {}", name, offset),
+            PngError::InvalidChunkLength { offset, length } => write!(f, "chunk length {} at offset {} exceeds 2^31 - 1", length, offset),
+            PngError::CrcMismatch { chunk, stored, computed } => write!(f, "CRC mismatch in {} chunk: stored {:08x}, computed {:08x}", chunk, stored, computed),
+            PngError::MalformedChunk { chunk, reason } => write!(f, "malformed {} chunk: {}", chunk, reason),
+            PngError::UnknownCriticalChunk(chunk) => write!(f, "unknown critical chunk {}", chunk),
+            PngError::MissingIhdr => write!(f, "IHDR is not the first chunk"),
+            PngError::InvalidIhdr(reason) => write!(f, "invalid IHDR: {}", reason),
+            PngError::ImageTooLarge { width, height, limit } => write!(f, "the pixels of a {}x{} image need more than {} bytes", width, height, limit),
+            PngError::UnsupportedColorType(t) => write!(f, "unsupported color type: {}", t),
+            PngError::MissingPlte => write!(f, "missing PLTE chunk for paletted image"),
+            PngError::PaletteIndexOutOfRange { index, entries } => write!(f, "palette index {} is out of range for {} entries", index, entries),
+            PngError::MissingIdat => write!(f, "missing IDAT chunk"),
+            PngError::NonConsecutiveIdat => write!(f, "IDAT chunks are not consecutive"),
+            PngError::MissingIend => write!(f, "missing IEND chunk"),
+            PngError::Inflate(reason) => write!(f, "could not inflate image data: {}", reason),
+            PngError::BadFilterType(t) => write!(f, "unrecognized filter type: {}", t),
+            PngError::TruncatedImageData { expected, found } => write!(f, "image data too short: expected {} bytes, found {}", expected, found),
+            PngError::Encode(reason) => write!(f, "could not encode image: {}", reason),
+        }
+    }
+}
+
+impl error::Error for PngError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            PngError::Io(e) => Some(e),
+            _ => None
+        }
+    }
+}
+
+impl From<io::Error> for PngError {
+    fn from(e: io::Error) -> Self {
+        PngError::Io(e)
+    }
+}
@@ -0,0 +1,354 @@
+use std::convert::TryInto;
+use super::{
+    buffer::{ImageBuffer, Sample},
+    error::PngError
+};
+
+// tags read by the accessors below [src: https://en.wikipedia.org/wiki/Exif]
+pub const MAKE: u16 = 0x010F;
+pub const MODEL: u16 = 0x0110;
+pub const ORIENTATION: u16 = 0x0112;
+pub const DATE_TIME: u16 = 0x0132;
+pub const EXIF_IFD: u16 = 0x8769;
+pub const GPS_IFD: u16 = 0x8825;
+pub const DATE_TIME_ORIGINAL: u16 = 0x9003;
+
+pub const GPS_LATITUDE_REF: u16 = 0x0001;
+pub const GPS_LATITUDE: u16 = 0x0002;
+pub const GPS_LONGITUDE_REF: u16 = 0x0003;
+pub const GPS_LONGITUDE: u16 = 0x0004;
+pub const GPS_ALTITUDE_REF: u16 = 0x0005;
+pub const GPS_ALTITUDE: u16 = 0x0006;
+
+// which image file directory a tag was found in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Ifd {
+    // IFD0, describing the image itself
+    Primary,
+
+    // IFD1, describing the thumbnail
+    Thumbnail,
+    Exif,
+    Gps
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ExifValue {
+    Byte(Vec<u8>),
+    Ascii(String),
+    Short(Vec<u16>),
+    Long(Vec<u32>),
+    Rational(Vec<(u32, u32)>),
+    SLong(Vec<i32>),
+    SRational(Vec<(i32, i32)>),
+
+    // UNDEFINED, and types the accessors have no use for, as raw bytes
+    Undefined(Vec<u8>)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExifEntry {
+    pub ifd: Ifd,
+    pub tag: u16,
+    pub value: ExifValue
+}
+
+// Orientation, as the transform needed to show the image upright
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Orientation {
+    Normal = 1,
+    FlipHorizontal = 2,
+    Rotate180 = 3,
+    FlipVertical = 4,
+    Transpose = 5,
+    Rotate90 = 6,
+    Transverse = 7,
+    Rotate270 = 8
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GpsPosition {
+    // degrees, negative south of the equator and west of Greenwich
+    pub latitude: f64,
+    pub longitude: f64,
+
+    // meters, negative below sea level
+    pub altitude: Option<f64>
+}
+
+// the contents of eXIf: a TIFF header followed by IFDs; the bytes are kept so the chunk can be written back unchanged [src: https://www.w3.org/TR/png-3/]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Exif {
+    pub bytes: Vec<u8>,
+    pub entries: Vec<ExifEntry>,
+
+    // where the Orientation value is stored, so it can be reset once applied
+    orientation_offset: Option<usize>
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    big_endian: bool
+}
+
+impl Exif {
+    // {byte order(2) "II" or "MM", 42(2), offset of IFD0(4)}
+    pub fn from_exif(bytes: &[u8]) -> Result<Exif, PngError> {
+        let big_endian = match bytes.get(0..4) {
+            Some(b"MM\0*") => true,
+            Some(b"II*\0") => false,
+            _ => return Err(PngError::malformed("eXIf", "missing TIFF header"))
+        };
+
+        let reader = Reader { bytes, big_endian };
+        let mut exif = Exif { bytes: bytes.to_vec(), entries: Vec::new(), orientation_offset: None };
+
+        let primary = reader.u32_at(4)? as usize;
+        let thumbnail = exif.read_ifd(&reader, primary, Ifd::Primary)?;
+
+        // the other IFDs are left out if they cannot be found, rather than losing IFD0 with them
+        if thumbnail != 0 {
+            exif.read_ifd(&reader, thumbnail, Ifd::Thumbnail).ok();
+        }
+
+        for (tag, ifd) in [(EXIF_IFD, Ifd::Exif), (GPS_IFD, Ifd::Gps)] {
+            if let Some(offset) = exif.long(Ifd::Primary, tag).and_then(|l| l.first().copied()) {
+                exif.read_ifd(&reader, offset as usize, ifd).ok();
+            }
+        }
+
+        Ok(exif)
+    }
+
+    // {entry count(2), entries of {tag(2), type(2), count(4), value or offset(4)}, next IFD offset(4)};
+    // only a missing entry count is an error, since real files often have entries pointing outside the chunk
+    fn read_ifd(&mut self, reader: &Reader, offset: usize, ifd: Ifd) -> Result<usize, PngError> {
+        let count = reader.u16_at(offset)? as usize;
+
+        for i in 0..count {
+            let entry = offset + 2 + i * 12;
+
+            // a table cut short keeps the entries before the cut
+            let (tag, kind, count) = match (reader.u16_at(entry), reader.u16_at(entry + 2), reader.u32_at(entry + 4)) {
+                (Ok(tag), Ok(kind), Ok(count)) => (tag, kind, count as usize),
+                _ => break
+            };
+
+            let size: usize = match kind {
+                1 | 2 | 6 | 7 => 1,
+                3 | 8 => 2,
+                4 | 9 | 11 => 4,
+                5 | 10 | 12 => 8,
+                _ => continue
+            };
+
+            // values of 4 bytes or fewer are stored in place of the offset; one outside the chunk, e.g. a MakerNote, is skipped
+            let value = size.checked_mul(count).and_then(|length| {
+                let start = if length <= 4 { entry + 8 } else { reader.u32_at(entry + 8).ok()? as usize };
+                Some((start, reader.slice(start, length).ok()?))
+            });
+            let (start, data) = match value {
+                Some(value) => value,
+                None => continue
+            };
+
+            if (ifd, tag, kind) == (Ifd::Primary, ORIENTATION, 3) {
+                self.orientation_offset = Some(start);
+            }
+
+            let value = match kind {
+                1 => ExifValue::Byte(data.to_vec()),
+                2 => ExifValue::Ascii(data.iter().take_while(|&&b| b != 0).map(|&b| b as char).collect()),
+                3 => ExifValue::Short(data.chunks(2).map(|c| reader.u16(c)).collect()),
+                4 => ExifValue::Long(data.chunks(4).map(|c| reader.u32(c)).collect()),
+                5 => ExifValue::Rational(data.chunks(8).map(|c| (reader.u32(&c[..4]), reader.u32(&c[4..]))).collect()),
+                9 => ExifValue::SLong(data.chunks(4).map(|c| reader.u32(c) as i32).collect()),
+                10 => ExifValue::SRational(data.chunks(8).map(|c| (reader.u32(&c[..4]) as i32, reader.u32(&c[4..]) as i32)).collect()),
+                _ => ExifValue::Undefined(data.to_vec())
+            };
+
+            self.entries.push(ExifEntry { ifd, tag, value });
+        }
+
+        Ok(reader.u32_at(offset + 2 + count * 12).map_or(0, |next| next as usize))
+    }
+
+    pub fn get(&self, ifd: Ifd, tag: u16) -> Option<&ExifValue> {
+        self.entries.iter().find(|e| e.ifd == ifd && e.tag == tag).map(|e| &e.value)
+    }
+
+    fn ascii(&self, ifd: Ifd, tag: u16) -> Option<&str> {
+        match self.get(ifd, tag)? {
+            ExifValue::Ascii(s) => Some(s.trim_end()),
+            _ => None
+        }
+    }
+
+    fn long(&self, ifd: Ifd, tag: u16) -> Option<Vec<u32>> {
+        match self.get(ifd, tag)? {
+            ExifValue::Long(l) => Some(l.clone()),
+            ExifValue::Short(s) => Some(s.iter().map(|&s| s as u32).collect()),
+            _ => None
+        }
+    }
+
+    fn rationals(&self, ifd: Ifd, tag: u16) -> Option<Vec<f64>> {
+        match self.get(ifd, tag)? {
+            ExifValue::Rational(r) => r.iter().map(|&(n, d)| if d == 0 { None } else { Some(n as f64 / d as f64) }).collect(),
+            _ => None
+        }
+    }
+
+    pub fn orientation(&self) -> Option<Orientation> {
+        Orientation::from_value(*self.long(Ifd::Primary, ORIENTATION)?.first()?)
+    }
+
+    // marks the image as upright, e.g. once the orientation has been applied to the pixels
+    pub fn reset_orientation(&mut self) {
+        if let Some(offset) = self.orientation_offset {
+            let one = if self.bytes.starts_with(b"MM") { [0, 1] } else { [1, 0] };
+            self.bytes[offset..offset + 2].copy_from_slice(&one);
+
+            for entry in self.entries.iter_mut().filter(|e| e.ifd == Ifd::Primary && e.tag == ORIENTATION) {
+                entry.value = ExifValue::Short(vec![1]);
+            }
+        }
+    }
+
+    pub fn make(&self) -> Option<&str> {
+        self.ascii(Ifd::Primary, MAKE)
+    }
+
+    pub fn model(&self) -> Option<&str> {
+        self.ascii(Ifd::Primary, MODEL)
+    }
+
+    // "YYYY:MM:DD HH:MM:SS" when the picture was taken, falling back to when the file was last changed
+    pub fn date_time_original(&self) -> Option<&str> {
+        self.ascii(Ifd::Exif, DATE_TIME_ORIGINAL).or_else(|| self.ascii(Ifd::Primary, DATE_TIME))
+    }
+
+    // latitude and longitude are stored as degrees, minutes and seconds
+    pub fn gps(&self) -> Option<GpsPosition> {
+        let degrees = |tag: u16, reference: u16, negative: &str| -> Option<f64> {
+            let dms = self.rationals(Ifd::Gps, tag)?;
+            let value = dms.first()? + dms.get(1).unwrap_or(&0.0) / 60.0 + dms.get(2).unwrap_or(&0.0) / 3600.0;
+
+            match self.ascii(Ifd::Gps, reference) {
+                Some(r) if r == negative => Some(-value),
+                _ => Some(value)
+            }
+        };
+
+        let altitude = self.rationals(Ifd::Gps, GPS_ALTITUDE).and_then(|a| a.first().copied()).map(|a| {
+            match self.get(Ifd::Gps, GPS_ALTITUDE_REF) {
+                Some(ExifValue::Byte(b)) if b.first() == Some(&1) => -a,
+                _ => a
+            }
+        });
+
+        Some(GpsPosition {
+            latitude: degrees(GPS_LATITUDE, GPS_LATITUDE_REF, "S")?,
+            longitude: degrees(GPS_LONGITUDE, GPS_LONGITUDE_REF, "W")?,
+            altitude
+        })
+    }
+}
+
+impl Orientation {
+    pub fn from_value(value: u32) -> Option<Orientation> {
+        match value {
+            1 => Some(Orientation::Normal),
+            2 => Some(Orientation::FlipHorizontal),
+            3 => Some(Orientation::Rotate180),
+            4 => Some(Orientation::FlipVertical),
+            5 => Some(Orientation::Transpose),
+            6 => Some(Orientation::Rotate90),
+            7 => Some(Orientation::Transverse),
+            8 => Some(Orientation::Rotate270),
+            _ => None
+        }
+    }
+
+    // orientations 5-8 swap width and height
+    pub fn swaps_dimensions(self) -> bool {
+        self as u8 >= 5
+    }
+
+    // where the pixel at (x, y) of a width x height image ends up in the upright image
+    pub fn map_position(self, x: u32, y: u32, width: u32, height: u32) -> (u32, u32) {
+        let (w, h) = (width, height);
+
+        match self {
+            Orientation::Normal => (x, y),
+            Orientation::FlipHorizontal => (w - 1 - x, y),
+            Orientation::Rotate180 => (w - 1 - x, h - 1 - y),
+            Orientation::FlipVertical => (x, h - 1 - y),
+            Orientation::Transpose => (y, x),
+            Orientation::Rotate90 => (h - 1 - y, x),
+            Orientation::Transverse => (h - 1 - y, w - 1 - x),
+            Orientation::Rotate270 => (y, w - 1 - x)
+        }
+    }
+
+    // the upright {x, y, width, height} of a non-empty region of a width x height image, e.g. an APNG frame
+    pub fn map_region(self, (x, y, region_width, region_height): (u32, u32, u32, u32), width: u32, height: u32) -> (u32, u32, u32, u32) {
+        let (x0, y0) = self.map_position(x, y, width, height);
+        let (x1, y1) = self.map_position(x + region_width - 1, y + region_height - 1, width, height);
+
+        (x0.min(x1), y0.min(y1), x0.abs_diff(x1) + 1, y0.abs_diff(y1) + 1)
+    }
+
+    // the upright image; rotations are clockwise
+    pub fn apply<T: Sample>(self, image: &ImageBuffer<T>) -> ImageBuffer<T> {
+        let (w, h) = (image.width(), image.height());
+        let (width, height) = if self.swaps_dimensions() { (h, w) } else { (w, h) };
+
+        let mut out: ImageBuffer<T> = ImageBuffer::new(width, height, image.layout());
+
+        for y in 0..height {
+            for x in 0..width {
+                let (sx, sy) = match self {
+                    Orientation::Normal => (x, y),
+                    Orientation::FlipHorizontal => (w - 1 - x, y),
+                    Orientation::Rotate180 => (w - 1 - x, h - 1 - y),
+                    Orientation::FlipVertical => (x, h - 1 - y),
+                    Orientation::Transpose => (y, x),
+                    Orientation::Rotate90 => (y, h - 1 - x),
+                    Orientation::Transverse => (w - 1 - y, h - 1 - x),
+                    Orientation::Rotate270 => (w - 1 - y, x)
+                };
+
+                out.pixel_samples_mut(x, y).copy_from_slice(image.pixel_samples(sx, sy));
+            }
+        }
+
+        out
+    }
+}
+
+impl<'a> Reader<'a> {
+    fn slice(&self, start: usize, length: usize) -> Result<&'a [u8], PngError> {
+        start.checked_add(length)
+             .and_then(|end| self.bytes.get(start..end))
+             .ok_or_else(|| PngError::malformed("eXIf", &format!("offset {} is out of bounds", start)))
+    }
+
+    fn u16_at(&self, offset: usize) -> Result<u16, PngError> {
+        self.slice(offset, 2).map(|b| self.u16(b))
+    }
+
+    fn u32_at(&self, offset: usize) -> Result<u32, PngError> {
+        self.slice(offset, 4).map(|b| self.u32(b))
+    }
+
+    fn u16(&self, b: &[u8]) -> u16 {
+        let b: [u8; 2] = b.try_into().unwrap();
+        if self.big_endian { u16::from_be_bytes(b) } else { u16::from_le_bytes(b) }
+    }
+
+    fn u32(&self, b: &[u8]) -> u32 {
+        let b: [u8; 4] = b.try_into().unwrap();
+        if self.big_endian { u32::from_be_bytes(b) } else { u32::from_le_bytes(b) }
+    }
+}
@@ -0,0 +1,82 @@
+// gamma values follow gAMA: the exponent that encoded linear light into samples, e.g. 1 / 2.2 [src: http://libpng.org/pub/png/spec/1.2/PNG-Chunks.html#C.gAMA]
+
+// gAMA stores gamma times 100000
+pub const GAMA_SCALE: f64 = 100000.0;
+
+// sRGB images are treated as having this file gamma when no exact transfer curve is used [src: http://libpng.org/pub/png/spec/1.2/PNG-Chunks.html#C.sRGB]
+pub const SRGB_FILE_GAMMA: f64 = 45455.0 / GAMA_SCALE;
+
+// a typical monitor; matches sRGB closely enough for display
+pub const SRGB_DISPLAY_GAMMA: f64 = 2.2;
+
+// maps 16-bit samples encoded with the file gamma to samples for a display with the given gamma [src: http://libpng.org/pub/png/spec/1.2/PNG-Decoders.html#D.Decoder-gamma-handling]
+#[derive(Clone, Debug, PartialEq)]
+pub struct GammaTable {
+    table: Vec<u16>
+}
+
+impl GammaTable {
+    pub fn new(file_gamma: f64, display_gamma: f64) -> GammaTable {
+        let exponent = 1.0 / (file_gamma * display_gamma);
+
+        // exponents within rounding of 1 leave samples as they are
+        if (exponent - 1.0).abs() < 1e-3 {
+            return GammaTable::identity()
+        }
+
+        GammaTable {
+            table: (0..=u16::MAX).map(|s| from_unit(to_unit(s).powf(exponent))).collect()
+        }
+    }
+
+    pub fn identity() -> GammaTable {
+        GammaTable { table: Vec::new() }
+    }
+
+    pub fn is_identity(&self) -> bool {
+        self.table.is_empty()
+    }
+
+    pub fn apply(&self, sample: u16) -> u16 {
+        if self.is_identity() {
+            sample
+        } else {
+            self.table[sample as usize]
+        }
+    }
+}
+
+// linear light in 0.0-1.0 from a sample encoded with the file gamma
+pub fn to_linear(sample: u16, file_gamma: f64) -> f64 {
+    to_unit(sample).powf(1.0 / file_gamma)
+}
+
+// the inverse of to_linear
+pub fn from_linear(linear: f64, file_gamma: f64) -> u16 {
+    from_unit(linear.clamp(0.0, 1.0).powf(file_gamma))
+}
+
+// the exact sRGB transfer curve [src: https://en.wikipedia.org/wiki/SRGB#Transfer_function_(%22gamma%22)]
+pub fn srgb_to_linear(value: f64) -> f64 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub fn linear_to_srgb(linear: f64) -> f64 {
+    if linear <= 0.0031308 {
+        linear * 12.92
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+pub fn to_unit(sample: u16) -> f64 {
+    sample as f64 / u16::MAX as f64
+}
+
+pub fn from_unit(value: f64) -> u16 {
+    (value.clamp(0.0, 1.0) * u16::MAX as f64).round() as u16
+}
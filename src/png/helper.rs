@@ -1,4 +1,4 @@
-pub fn bytes_as_4bit(bytes: &Vec<u8>) -> Vec<u8> {
+pub fn bytes_as_4bit(bytes: &[u8]) -> Vec<u8> {
     let new_data: Vec<u8> = bytes.iter()
                                     .map(|&b| b.to_u4()[..].to_vec())
                                     .collect::<Vec<Vec<u8>>>()
@@ -10,7 +10,7 @@ pub fn bytes_as_4bit(bytes: &Vec<u8>) -> Vec<u8> {
     new_data
 }
 
-pub fn bytes_as_2bit(bytes: &Vec<u8>) -> Vec<u8> {
+pub fn bytes_as_2bit(bytes: &[u8]) -> Vec<u8> {
     let new_data: Vec<u8> = bytes.iter()
                                     .map(|&b| b.to_u2()[..].to_vec())
                                     .collect::<Vec<Vec<u8>>>()
@@ -22,7 +22,7 @@ pub fn bytes_as_2bit(bytes: &Vec<u8>) -> Vec<u8> {
     new_data
 }
 
-pub fn bytes_as_1bit(bytes: &Vec<u8>) -> Vec<u8> {
+pub fn bytes_as_1bit(bytes: &[u8]) -> Vec<u8> {
     let new_data: Vec<u8> = bytes.iter()
                                     .map(|&b| b.to_u1()[..].to_vec())
                                     .collect::<Vec<Vec<u8>>>()
@@ -34,7 +34,17 @@ pub fn bytes_as_1bit(bytes: &Vec<u8>) -> Vec<u8> {
     new_data
 }
 
-pub fn bytes_from_16bit_to_8bit(bytes: &Vec<u16>) -> Vec<u8> {
+// unpacks sub-byte values without scaling them, e.g. for palette indices
+pub fn bytes_as_indices(bytes: &[u8], depth: u8) -> Vec<u8> {
+    let per_byte = 8 / depth;
+    let mask = ((1u16 << depth) - 1) as u8;
+
+    bytes.iter()
+         .flat_map(|&b| (0..per_byte).map(move |i| (b >> (8 - depth * (i + 1))) & mask))
+         .collect()
+}
+
+pub fn bytes_from_16bit_to_8bit(bytes: &[u16]) -> Vec<u8> {
     let new_data: Vec<u8> = bytes.iter()
                                   .map(|b| b.to_u8())
                                   .collect::<Vec<u8>>();
@@ -42,7 +52,7 @@ pub fn bytes_from_16bit_to_8bit(bytes: &Vec<u16>) -> Vec<u8> {
     new_data
 }
 
-pub fn bytes_as_16bit(bytes: &Vec<u8>) -> Vec<u16> {
+pub fn bytes_as_16bit(bytes: &[u8]) -> Vec<u16> {
     let new_data: Vec<u16> = bytes.chunks(2)
                                   .map(|b| b.to_u16())
                                   .collect::<Vec<u16>>();
@@ -50,18 +60,9 @@ pub fn bytes_as_16bit(bytes: &Vec<u8>) -> Vec<u16> {
     new_data
 }
 
+// rounds to the nearest value; truncating made every blend lean towards a
 pub fn lerp_u8(a: u8, b: u8, f: f32) -> u8 {
-    if a == b {
-        a
-    } else if a < b {
-        let diff = b - a;
-
-        a + (diff as f32 * f) as u8
-    } else {
-        let diff = a - b;
-
-        a - (diff as f32 * f) as u8
-    }
+    (a as f32 + (b as f32 - a as f32) * f.clamp(0.0, 1.0)).round() as u8
 }
 
 pub trait UXBigger {
@@ -70,13 +71,13 @@ pub trait UXBigger {
 
 impl UXBigger for &[u8] {
     fn to_u16(&self) -> u16 {
-        (self[0] as u16 * 0x0100) + (self[1] as u16 * 0x0001)
+        (self[0] as u16 * 0x0100) + (self[1] as u16)
     }
 }
 
 impl UXBigger for [u8; 2] {
     fn to_u16(&self) -> u16 {
-        (self[0] as u16 * 0x0100) + (self[1] as u16 * 0x0001)
+        (self[0] as u16 * 0x0100) + (self[1] as u16)
     }
 }
 
@@ -107,6 +108,7 @@ impl UXSmaller for u16 {
 
 impl UXSmaller for u8 {
     fn to_u8(&self) -> u8 { *self }
+    #[allow(clippy::needless_range_loop)]
     fn to_u4(&self) -> [u8; 2] {
         let mut ret: [u8; 2] = [0; 2];
         let mut index_bit = 0b11110000u8;
@@ -120,6 +122,7 @@ impl UXSmaller for u8 {
         ret
     }
 
+    #[allow(clippy::needless_range_loop)]
     fn to_u2(&self) -> [u8; 4] {
         let mut ret: [u8; 4] = [0; 4];
         let mut index_bit = 0b11000000u8;
@@ -133,6 +136,7 @@ impl UXSmaller for u8 {
         ret
     }
 
+    #[allow(clippy::needless_range_loop)]
     fn to_u1(&self) -> [u8; 8] {
         let mut ret: [u8; 8] = [0; 8];
         let mut index_bit = 0b10000000u8;
@@ -145,4 +149,41 @@ impl UXSmaller for u8 {
 
         ret
     }
-}
\ No newline at end of file
+}
+
+// [src: http://libpng.org/pub/png/spec/1.2/PNG-CRCAppendix.html]
+const CRC_TABLE: [u32; 256] = make_crc_table();
+
+const fn make_crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut n = 0;
+
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+
+        while k < 8 {
+            c = if c & 1 == 1 { 0xEDB88320 ^ (c >> 1) } else { c >> 1 };
+            k += 1;
+        }
+
+        table[n] = c;
+        n += 1;
+    }
+
+    table
+}
+
+pub fn update_crc32(crc: u32, bytes: &[u8]) -> u32 {
+    let mut c = crc;
+
+    for &b in bytes {
+        c = CRC_TABLE[((c ^ b as u32) & 0xFF) as usize] ^ (c >> 8);
+    }
+
+    c
+}
+
+pub fn crc32(bytes: &[u8]) -> u32 {
+    update_crc32(0xFFFFFFFF, bytes) ^ 0xFFFFFFFF
+}
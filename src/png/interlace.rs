@@ -0,0 +1,58 @@
+// (x_start, y_start, x_step, y_step) for each of the 7 passes [src: http://libpng.org/pub/png/spec/1.2/PNG-DataRep.html#DR.Interlaced-data-order]
+const ADAM7: [(u32, u32, u32, u32); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Pass {
+    pub x_start: u32,
+    pub y_start: u32,
+    pub x_step: u32,
+    pub y_step: u32,
+
+    // size of the reduced image
+    pub width: u32,
+    pub height: u32
+}
+
+impl Pass {
+    pub fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    // maps a position in the reduced image to its position in the full image
+    pub fn position(&self, x: u32, y: u32) -> (u32, u32) {
+        (self.x_start + x * self.x_step, self.y_start + y * self.y_step)
+    }
+}
+
+pub fn passes(width: u32, height: u32, interlace_method: u8) -> Vec<Pass> {
+    if interlace_method == 0 {
+        return vec![Pass { x_start: 0, y_start: 0, x_step: 1, y_step: 1, width, height }]
+    }
+
+    ADAM7.iter()
+         .map(|&(x_start, y_start, x_step, y_step)| Pass {
+             x_start,
+             y_start,
+             x_step,
+             y_step,
+             width: reduced_size(width, x_start, x_step),
+             height: reduced_size(height, y_start, y_step)
+         })
+         .collect()
+}
+
+fn reduced_size(size: u32, start: u32, step: u32) -> u32 {
+    if size <= start {
+        0
+    } else {
+        (size - start).div_ceil(step)
+    }
+}
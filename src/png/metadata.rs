@@ -0,0 +1,208 @@
+use std::{convert::TryInto, fmt};
+use super::error::PngError;
+
+// tIME, in UTC [src: http://libpng.org/pub/png/spec/1.2/PNG-Chunks.html#C.tIME]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Timestamp {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+
+    // 60 allows for leap seconds
+    pub second: u8
+}
+
+impl Timestamp {
+    // {year(2), month(1), day(1), hour(1), minute(1), second(1)}
+    pub fn from_time(data: &[u8]) -> Result<Timestamp, PngError> {
+        if data.len() != 7 {
+            return Err(PngError::malformed("tIME", "expected 7 bytes"))
+        }
+
+        let t = Timestamp {
+            year: u16::from_be_bytes(data[0..2].try_into().unwrap()),
+            month: data[2],
+            day: data[3],
+            hour: data[4],
+            minute: data[5],
+            second: data[6]
+        };
+
+        if !(1..=12).contains(&t.month) || !(1..=31).contains(&t.day) || t.hour > 23 || t.minute > 59 || t.second > 60 {
+            return Err(PngError::malformed("tIME", &format!("{} is not a valid time", t)))
+        }
+
+        Ok(t)
+    }
+
+    pub fn to_time(self) -> Vec<u8> {
+        let mut data = self.year.to_be_bytes().to_vec();
+        data.extend_from_slice(&[self.month, self.day, self.hour, self.minute, self.second]);
+
+        data
+    }
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC", self.year, self.month, self.day, self.hour, self.minute, self.second)
+    }
+}
+
+// sBIT: the number of bits that were significant in the original samples, one per channel in file order;
+// paletted images give red, green and blue of the palette entries [src: http://libpng.org/pub/png/spec/1.2/PNG-Chunks.html#C.sBIT]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SignificantBits {
+    pub bits: Vec<u8>
+}
+
+impl SignificantBits {
+    pub fn from_sbit(color_type: u8, depth: u8, data: &[u8]) -> Result<SignificantBits, PngError> {
+        let (channels, sample_depth) = match color_type {
+            0 => (1, depth),
+            2 => (3, depth),
+            3 => (3, 8),
+            4 => (2, depth),
+            _ => (4, depth)
+        };
+
+        if data.len() != channels {
+            return Err(PngError::malformed("sBIT", &format!("expected {} bytes for color type {}", channels, color_type)))
+        }
+
+        if data.iter().any(|&b| b == 0 || b > sample_depth) {
+            return Err(PngError::malformed("sBIT", &format!("significant bits must be 1-{}", sample_depth)))
+        }
+
+        Ok(SignificantBits { bits: data.to_vec() })
+    }
+
+    pub fn to_sbit(&self) -> Vec<u8> {
+        self.bits.clone()
+    }
+
+    // a decoded 16-bit sample reduced to its significant bits and spread over 0-65535 again
+    pub fn rescale(sample: u16, bits: u8) -> u16 {
+        if bits >= 16 {
+            return sample
+        }
+
+        let max = (1u32 << bits) - 1;
+        let significant = sample as u32 >> (16 - bits);
+
+        ((significant * 0xFFFF + max / 2) / max) as u16
+    }
+}
+
+// sPLT [src: http://libpng.org/pub/png/spec/1.2/PNG-Chunks.html#C.sPLT]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SuggestedPalette {
+    pub name: String,
+
+    // 8 or 16; the samples below are at this depth
+    pub depth: u8,
+    pub entries: Vec<SuggestedEntry>
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SuggestedEntry {
+    pub r: u16,
+    pub g: u16,
+    pub b: u16,
+    pub a: u16,
+
+    // relative, 0 if unknown
+    pub frequency: u16
+}
+
+impl SuggestedPalette {
+    // {name(1-79), null(1), depth(1), entries of {r, g, b, a} at depth then frequency(2)}
+    pub fn from_splt(data: &[u8]) -> Result<SuggestedPalette, PngError> {
+        let null = match data.iter().position(|&b| b == 0) {
+            Some(i) if (1..=79).contains(&i) => i,
+            _ => return Err(PngError::malformed("sPLT", "palette name must be 1-79 bytes followed by a null"))
+        };
+
+        let depth = *data.get(null + 1).ok_or_else(|| PngError::malformed("sPLT", "missing sample depth"))?;
+        let entry_size = match depth {
+            8 => 6,
+            16 => 10,
+            _ => return Err(PngError::malformed("sPLT", &format!("invalid sample depth {}", depth)))
+        };
+
+        let entries = &data[null + 2..];
+        if !entries.len().is_multiple_of(entry_size) {
+            return Err(PngError::malformed("sPLT", "entries do not match the sample depth"))
+        }
+
+        let entries = entries.chunks(entry_size).map(|e| {
+            let u16_at = |i: usize| u16::from_be_bytes(e[i..i + 2].try_into().unwrap());
+
+            match depth {
+                8 => SuggestedEntry { r: e[0] as u16, g: e[1] as u16, b: e[2] as u16, a: e[3] as u16, frequency: u16_at(4) },
+                _ => SuggestedEntry { r: u16_at(0), g: u16_at(2), b: u16_at(4), a: u16_at(6), frequency: u16_at(8) }
+            }
+        }).collect();
+
+        Ok(SuggestedPalette { name: data[..null].iter().map(|&b| b as char).collect(), depth, entries })
+    }
+
+    pub fn to_splt(&self) -> Vec<u8> {
+        let mut data: Vec<u8> = self.name.chars().map(|c| c as u8).collect();
+        data.extend_from_slice(&[0, self.depth]);
+
+        for e in &self.entries {
+            for &s in &[e.r, e.g, e.b, e.a] {
+                match self.depth {
+                    8 => data.push(s as u8),
+                    _ => data.extend_from_slice(&s.to_be_bytes())
+                }
+            }
+            data.extend_from_slice(&e.frequency.to_be_bytes());
+        }
+
+        data
+    }
+}
+
+// sTER: the image is two views side by side, separated by padding columns [src: https://www.w3.org/TR/png-3/]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StereoLayout {
+    // the right eye's view is on the left
+    CrossFuse,
+
+    // the left eye's view is on the left
+    DivergingFuse
+}
+
+impl StereoLayout {
+    pub fn from_ster(data: &[u8]) -> Result<StereoLayout, PngError> {
+        match data {
+            [0] => Ok(StereoLayout::CrossFuse),
+            [1] => Ok(StereoLayout::DivergingFuse),
+            _ => Err(PngError::malformed("sTER", "expected a mode of 0 or 1"))
+        }
+    }
+
+    pub fn to_ster(self) -> Vec<u8> {
+        match self {
+            StereoLayout::CrossFuse => vec![0],
+            StereoLayout::DivergingFuse => vec![1]
+        }
+    }
+}
+
+// hIST: how often each palette entry is used, one per entry [src: http://libpng.org/pub/png/spec/1.2/PNG-Chunks.html#C.hIST]
+pub fn histogram_from_hist(data: &[u8], entries: usize) -> Result<Vec<u16>, PngError> {
+    if entries == 0 {
+        return Err(PngError::malformed("hIST", "hIST must come after PLTE"))
+    }
+
+    if data.len() != entries * 2 {
+        return Err(PngError::malformed("hIST", &format!("expected {} entries to match the palette", entries)))
+    }
+
+    Ok(data.chunks(2).map(|c| u16::from_be_bytes(c.try_into().unwrap())).collect())
+}
@@ -1,6 +1,21 @@
+#[allow(clippy::module_inception)]
 pub mod png;
+pub mod apng;
+pub mod buffer;
 pub mod chunk;
+pub mod error;
 pub mod color;
+pub mod colorspace;
+pub mod composite;
+pub mod decoder;
+pub mod display;
+pub mod encoder;
+pub mod exif;
 pub mod pixel;
 pub mod scanline;
-pub mod helper;
\ No newline at end of file
+pub mod text;
+pub mod helper;
+pub mod interlace;
+pub mod metadata;
+pub mod gamma;
+pub mod zlib;
\ No newline at end of file
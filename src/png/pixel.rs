@@ -1,18 +1,19 @@
-use super::color::Color;
+use super::color::{Color, Color16};
 
 #[derive(Clone, Debug, Copy)]
 pub struct Pixel {
     pub pos: (u32, u32),
-    pub color: Color,
+    pub color: Color16,
     pub palette_index: Option<u8>
 }
 
 impl Pixel {
-    pub fn from_palette_index(pos: (u32, u32), index: usize, palette: &Vec<Color>) -> Pixel {
+    // indices past the end of the palette fall back to transparent black
+    pub fn from_palette_index(pos: (u32, u32), index: usize, palette: &[Color]) -> Pixel {
         Pixel {
             pos,
-            color: palette[index],
-            palette_index: None
+            color: palette.get(index).map(Color16::from_8bit).unwrap_or_default(),
+            palette_index: Some(index as u8)
         }
     }
 }
\ No newline at end of file
@@ -1,19 +1,38 @@
-use std::{cmp::{max, min}, convert::TryInto, fs, mem::transmute, str};
-use inflate::inflate_bytes_zlib;
+use std::{cmp::{max, min}, convert::TryInto, fs::File, io::{BufReader, Cursor, Read}, str};
 
 pub use super::{
+    apng::{AnimationControl, AnimationOptions, Frame},
+    buffer::{ChannelLayout, ImageBuffer},
     chunk::*,
-    color::Color,
+    color::{Color, Color16},
+    colorspace::{Chromaticities, IccProfile},
+    composite::Background,
+    decoder::{DecodeOptions, PngDecoder, Row, DEFAULT_MAX_IMAGE_BYTES},
+    error::PngError,
+    exif::Exif,
+    interlace::{self, Pass},
+    metadata::{self, SignificantBits, StereoLayout, SuggestedPalette, Timestamp},
     scanline::*,
-    pixel::*
+    pixel::*,
+    text::{TextChunk, TextKind},
+    zlib
 };
 
+use super::apng::PendingFrames;
+
+// check header [src: https://en.wikipedia.org/wiki/Portable_Network_Graphics#File_header]
+pub const SIGNATURE: [u8; 8] = [0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A];
+
 // structs
 #[derive(Clone, Debug, Default)]
 pub struct Png {
-    pub filepath: String,
+    // where the image was read from, if it came from a file
+    pub filepath: Option<String>,
     pub chunks: Vec<Chunk>,
 
+    // ancillary chunks that could not be read are left out of chunks and the fields below; this says why, in file order
+    pub warnings: Vec<String>,
+
     // IHDR values
     pub width: u32,
     pub height: u32,
@@ -32,394 +51,663 @@ pub struct Png {
     // sRGB
     pub rendering_intent: u8,
 
-    // pHYS
+    // cHRM
+    pub chromaticities: Option<Chromaticities>,
+
+    // iCCP
+    pub icc_profile: Option<IccProfile>,
+
+    // pHYs
     pub ppu_x: u32,
     pub ppu_y: u32,
     pub unit_spec: u8,
 
-    // tRNS
+    // tRNS color keys, as samples at the image bit depth (alpha is unused)
     pub gray_lvl: Option<u16>,
-    pub truecolor_alpha: Option<Color>,
+    pub truecolor_alpha: Option<Color16>,
+
+    // bKGD
+    pub background: Option<Background>,
+
+    // tEXt, zTXt and iTXt, in file order
+    pub text_chunks: Vec<TextChunk>,
+
+    // tIME
+    pub modified: Option<Timestamp>,
+
+    // sBIT
+    pub significant_bits: Option<SignificantBits>,
+
+    // hIST, one frequency per palette entry
+    pub histogram: Option<Vec<u16>>,
+
+    // sPLT
+    pub suggested_palettes: Vec<SuggestedPalette>,
+
+    // sTER
+    pub stereo: Option<StereoLayout>,
+
+    // eXIf
+    pub exif: Option<Exif>,
+
+    // acTL; None for still images, including files whose acTL comes too late to count
+    pub animation: Option<AnimationControl>,
+
+    // fcTL and fdAT; the default image is the first frame unless its fcTL is missing, in which case it is not part of the animation
+    pub frames: Vec<Frame>,
+    pub(crate) pending_frames: PendingFrames,
 
     // IDAT
-    pub pixels: Vec<Pixel>,
+    pub pixels: ImageBuffer,
     pub filters: Vec<u8>,
 
+    // the palette index of every pixel, for color type 3; takes precedence over pixels when encoding
+    pub indices: Option<ImageBuffer<u8>>,
+
     pub aspect_ratio: f32, 
 
-    raw_bytes: Vec<u8>,
+    // bytes read while decoding
+    size: usize,
+
+    // the limit the image was decoded with, for what is built from it later, e.g. APNG frames
+    max_image_bytes: Option<usize>,
 }
 
 // impl
 impl Png {
-    pub fn read_from_file(filepath: &str) -> Result<Png, String> {
-        let mut out: Png = Png::default();
+    pub fn read_from_file(filepath: &str) -> Result<Png, PngError> {
+        let mut out = Png::from_reader(BufReader::new(File::open(filepath)?))?;
+        out.filepath = Some(filepath.to_owned());
 
-        let bytes: Vec<u8> = fs::read(filepath).expect(&format!("{} could not be read", filepath));
-        out.raw_bytes = bytes;
+        Ok(out)
+    }
 
-        let img_buffer: &[u8] = out.raw_bytes.as_slice();
+    pub fn from_bytes(bytes: &[u8]) -> Result<Png, PngError> {
+        Png::from_reader(Cursor::new(bytes))
+    }
 
-        // check header [src: https://en.wikipedia.org/wiki/Portable_Network_Graphics#File_header]
-        let expected_header: [u8; 8] = [0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A];
+    // every entry point decodes through here, one row at a time
+    pub fn from_reader<R: Read>(reader: R) -> Result<Png, PngError> {
+        Png::from_reader_with(reader, &DecodeOptions::default())
+    }
 
-        if img_buffer[0..8] != expected_header {
-            return Err(format!("Invalid file header for file: {}", filepath))
+    pub fn from_reader_with<R: Read>(reader: R, options: &DecodeOptions) -> Result<Png, PngError> {
+        let mut decoder = PngDecoder::new(reader)?;
+
+        // palette entries are expanded to their (tRNS) colors; a color key adds an alpha channel
+        let info = decoder.info();
+        let color_key = options.apply_transparency && (info.gray_lvl.is_some() || info.truecolor_alpha.is_some());
+
+        let layout = match info.color_type {
+            0 if color_key => ChannelLayout::GrayAlpha,
+            0 => ChannelLayout::Gray,
+            2 if color_key => ChannelLayout::Rgba,
+            2 => ChannelLayout::Rgb,
+            4 => ChannelLayout::GrayAlpha,
+            _ => ChannelLayout::Rgba
+        };
+        // IHDR is untrusted, so its size is checked before anything is allocated; the indices count towards the same limit
+        let mut pixels: ImageBuffer = ImageBuffer::try_new(info.width, info.height, layout, options.max_image_bytes)?;
+        let mut indices: Option<ImageBuffer<u8>> = match info.color_type {
+            3 => Some(ImageBuffer::try_new(info.width, info.height, ChannelLayout::Gray, options.max_image_bytes - pixels.as_raw().len() * 2)?),
+            _ => None
+        };
+        let mut filters: Vec<u8> = Vec::new();
+
+        while let Some(row) = decoder.next_row()? {
+            filters.push(row.scanline.filter);
+            decoder.info().write_row(&mut pixels, indices.as_mut(), &row)?;
         }
 
-        // check for end of image (IEND....) [src: https://en.wikipedia.org/wiki/Portable_Network_Graphics#Critical_chunks]
-        let expected_eoi: [u8; 8] = [0x49, 0x45, 0x4E, 0x44, 0xAE, 0x42, 0x60, 0x82];
+        let size = decoder.bytes_read();
+
+        let mut out = decoder.into_info();
+        out.pixels = pixels;
+        out.indices = indices;
+        out.filters = filters;
+        out.size = size;
+        out.max_image_bytes = Some(options.max_image_bytes);
 
-        if img_buffer[img_buffer.len()-8..img_buffer.len()] != expected_eoi {
-            return Err(format!("Invalid EOF chunk for file: {}", filepath))
+        if let Err(e) = out.decode_frames() {
+            out.drop_animation(e);
+        }
+
+        if options.rescale_significant_bits {
+            out.rescale_significant_bits();
+        }
+        if options.apply_orientation {
+            out.apply_orientation();
         }
-        out.filepath = filepath.to_owned();
 
-        let chunks = Chunk::from_bytes(&out.raw_bytes);
-        out.chunks = chunks;
+        Ok(out)
+    }
 
-        for chunk in &out.chunks {
-            match &chunk.name[..] {
-                "IHDR" => {
-                    // {width(4), height(4), depth(1), color_type(1), compression(1), filter(1), interface(1)} [src: https://en.wikipedia.org/wiki/Portable_Network_Graphics#Critical_chunks]
-                    out.width = unsafe {
-                        transmute::<[u8; 4], u32>(chunk.data[0..4].try_into().unwrap()).to_be() 
-                    };
+    // turns the pixels upright; ppu, the palette indices and APNG frames follow, and the orientation is reset so it is not applied twice
+    pub fn apply_orientation(&mut self) {
+        let orientation = match self.exif.as_ref().and_then(|e| e.orientation()) {
+            Some(o) => o,
+            None => return
+        };
 
-                    out.height = unsafe {
-                        transmute::<[u8; 4], u32>(chunk.data[4..8].try_into().unwrap()).to_be() 
-                    };
+        self.pixels = orientation.apply(&self.pixels);
+        self.indices = self.indices.as_ref().map(|i| orientation.apply(i));
 
-                    out.depth = chunk.data[8];
-                    out.color_type = chunk.data[9];
-                    out.compression_type = chunk.data[10];
-                    out.filter = chunk.data[11];
-                    out.interface = chunk.data[12];
+        // APNG frames turn with the canvas, regions included
+        for frame in &mut self.frames {
+            let c = &mut frame.control;
+            (c.x_offset, c.y_offset, c.width, c.height) = orientation.map_region((c.x_offset, c.y_offset, c.width, c.height), self.width, self.height);
 
-                    out.aspect_ratio = (out.width as f32) / (out.height as f32);
-                },
-                "PLTE" => {
-                    for c in chunk.data.chunks(3).into_iter() {
-                        out.colors.push(Color { r: c[0], g: c[1], b: c[2], a: 255 });
-                    }
+            frame.pixels = orientation.apply(&frame.pixels);
+        }
 
-                    out.colors.dedup();
-                },
-                "gAMA" => {
-                    out.gamma = unsafe {
-                        transmute::<[u8; 4], u32>(chunk.data[..].try_into().unwrap()).to_be() 
-                    };
-                },
-                "sRGB" => {
-                    out.rendering_intent = chunk.data[0];
-                },
-                "pHYS" => {
-                    out.ppu_x = unsafe {
-                        transmute::<[u8; 4], u32>(chunk.data[0..4].try_into().unwrap()).to_be() 
-                    };
-
-                    out.ppu_y = unsafe {
-                        transmute::<[u8; 4], u32>(chunk.data[4..8].try_into().unwrap()).to_be() 
-                    };
-
-                    out.unit_spec = chunk.data[8];
-                },
-                "tRNS" => {
-                    match out.color_type {
-                        0 => {
-                            out.gray_lvl = unsafe {
-                                Some(transmute::<[u8; 2], u16>(chunk.data[..].try_into().unwrap()).to_be())
-                            };
-                        },
-                        2 => {
-                            let c: Color;
-
-                            let r = unsafe {
-                                transmute::<[u8; 2], u16>(chunk.data[0..2].try_into().unwrap()).to_be() as u8 // this is stupid but it works i think
-                            };
-                            let g = unsafe {
-                                transmute::<[u8; 2], u16>(chunk.data[2..4].try_into().unwrap()).to_be() as u8 // ...
-                            };
-                            let b = unsafe {
-                                transmute::<[u8; 2], u16>(chunk.data[4..6].try_into().unwrap()).to_be() as u8 // ...
-                            };
-
-                            c = Color { r, g, b, a: 255 };
-
-                            out.truecolor_alpha = Some(c);
-                        },
-                        3 => {
-                            // len(tRNS <= PLTE) [src: http://libpng.org/pub/png/spec/1.2/PNG-Chunks.html#C.tRNS]
-                            for i in 0..min(chunk.data.len(), out.colors.len()) {
-                                out.colors[i].a = chunk.data[i];
-                            }
-                        },
-                        _ => {}
+        if orientation.swaps_dimensions() {
+            std::mem::swap(&mut self.width, &mut self.height);
+            std::mem::swap(&mut self.ppu_x, &mut self.ppu_y);
+            self.aspect_ratio = self.width as f32 / self.height as f32;
+        }
+
+        if let Some(exif) = self.exif.as_mut() {
+            exif.reset_orientation();
+        }
+    }
+
+    // e.g. 5-bit samples stored in 8 bits become exact 5-bit levels rather than whatever the low bits held;
+    // for paletted images this applies to the expanded pixels, the palette itself is kept as stored
+    pub fn rescale_significant_bits(&mut self) {
+        let bits = match &self.significant_bits {
+            Some(s) => s.bits.clone(),
+            None => return
+        };
+
+        // APNG frames are stored at the same depth, so they are rescaled too
+        for pixels in std::iter::once(&mut self.pixels).chain(self.frames.iter_mut().map(|f| &mut f.pixels)) {
+            for y in 0..pixels.height() {
+                for x in 0..pixels.width() {
+                    for (sample, &b) in pixels.pixel_samples_mut(x, y).iter_mut().zip(bits.iter()) {
+                        *sample = SignificantBits::rescale(*sample, b);
                     }
-                },
-                "IDAT" => {
-                    let inflated_bytes = inflate_bytes_zlib(&chunk.data).unwrap();
-                    
-                    out.pixels = Vec::new();
-                    match out.color_type {
-                        0 => { 
-                            let mut scanlines: Vec<Scanline> = inflated_bytes.chunks((out.width as usize / 8 * out.depth as usize) + 1)
-                                                                             .map(|c| Scanline::from_bytes(&c.to_vec(), 1, out.depth))
-                                                                             .collect();
-                                
-
-                            for i in 0..scanlines.len() {
-                                out.filters.push(scanlines[i].filter);
-                                let prev_line = if i > 0 { Some(scanlines[i - 1].pixel_bytes.clone()) } else { None };
-
-                                scanlines[i].unfilter(if out.filters[i] == 1 { None } else { prev_line.as_ref() });
-                            }
-
-                            for y in 0..(out.height as u32) {
-                                for x in 0..(out.width as u32) {
-                                    let c: Color = {
-                                        let g = scanlines[y as usize].pixel_bytes[x as usize];
-
-                                        Color::grayscale(g, 255)
-                                    };
-
-                                    out.pixels.push(Pixel {
-                                        color: c,
-                                        palette_index: None,
-                                        pos: (x, y)
-                                    });
-                                }
-                            }
-                        },
-                        2 => {
-                            let line_size = (out.width as usize * if out.depth == 16 { 6 } else { 3 }) as usize + 1;
-
-                            let mut scanlines: Vec<Scanline> = {
-                                if out.interface == 0 {
-                                    inflated_bytes.chunks((out.width as usize / 8 * out.depth as usize) + 1)
-                                                  .map(|c| Scanline::from_bytes(&c.to_vec(), 1, out.depth))
-                                                  .collect()
-                                } else {
-                                    let adam7 = [
-                                        [1, 6, 4, 6, 2, 6, 4, 6],
-                                        [7, 7, 7, 7, 7, 7, 7, 7],
-                                        [5, 6, 5, 6, 5, 6, 5, 6],
-                                        [7, 7, 7, 7, 7, 7, 7, 7],
-                                        [3, 6, 4, 6, 3, 6, 4, 6],
-                                        [7, 7, 7, 7, 7, 7, 7, 7],
-                                        [5, 6, 5, 6, 5, 6, 5, 6],
-                                        [7, 7, 7, 7, 7, 7, 7, 7],
-                                    ];
-
-                                    let pixel_width = (out.depth as f32 / 8.0).ceil() as usize;
-                                    let line_width = 1 + (out.width as usize * pixel_width);
-
-                                    let lines: Vec<Scanline> = Vec::new();
-
-                                    let pass_sizes: Vec<usize> = [
-                                        1.0 / 64.0,
-                                        1.0 / 64.0,
-                                        1.0 / 32.0,
-                                        1.0 / 16.0,
-                                        1.0 / 8.0,
-                                        1.0 / 4.0,
-                                        1.0 / 2.0,
-                                    ].iter().map(|s| (out.height as f32 * out.width as f32 * s).ceil() as usize * pixel_width).collect();
-
-                                    let pass_widths: Vec<usize> = [
-                                        1.0 / 64.0,
-                                        1.0 / 64.0,
-                                        1.0 / 32.0,
-                                        1.0 / 16.0,
-                                        1.0 / 8.0,
-                                        1.0 / 4.0,
-                                        1.0 / 2.0,
-                                    ].iter().map(|s| (out.width as f32 * s).ceil() as usize * pixel_width).collect();
-
-                                    let offsets = {
-                                        let mut o = [0; 7];
-
-                                        for i in 0..7 {
-                                            o[i] = pass_sizes[0..i].iter().sum()
-                                        }
-
-                                        o
-                                    };
-
-                                    let scanlines = {
-                                        let mut s: Vec<Scanline> = Vec::new();
-
-                                        let chunks = vec![
-                                            inflated_bytes[0..offsets[1]].to_vec(),
-                                            inflated_bytes[offsets[1]..offsets[2]].to_vec(),
-                                            inflated_bytes[offsets[2]..offsets[3]].to_vec(),
-                                            inflated_bytes[offsets[3]..offsets[4]].to_vec(),
-                                            inflated_bytes[offsets[4]..offsets[5]].to_vec(),
-                                            inflated_bytes[offsets[5]..offsets[6]].to_vec(),
-                                            inflated_bytes[offsets[6]..].to_vec()
-                                        ];
-
-                                        for i in 0..6 {
-                                            for l in (0..chunks[i].len()).step_by(pass_widths[i]) {
-                                                s.push(Scanline::from_bytes(&chunks[i][l..l + pass_widths[i]].to_vec(), pixel_width as u8, out.depth));
-                                            }
-                                        }
-
-                                        s
-                                    };
-
-                                    println!("{:?}", scanlines[0].raw_bytes);
-
-                                    lines
-                                }
-                            };
-                            
-                            for i in 0..scanlines.len() {
-                                out.filters.push(scanlines[i].filter);
-                                let prev_line = if i > 0 { Some(scanlines[i - 1].pixel_bytes.clone()) } else { None };
-
-                                scanlines[i].unfilter(if out.filters[i] == 1 { None } else { prev_line.as_ref() });
-                            }
-
-                            for y in 0..(out.height as u32) {
-                                for x in (0..(out.width * 3 as u32)).step_by(3) {
-                                    let c: Color = {
-                                        let r = scanlines[y as usize].pixel_bytes[x as usize];
-                                        let g = scanlines[y as usize].pixel_bytes[x as usize + 1];
-                                        let b = scanlines[y as usize].pixel_bytes[x as usize + 2];
-
-                                        Color { r, g, b, a: 255u8 }
-                                    };
-
-                                    out.pixels.push(Pixel {
-                                        color: c,
-                                        palette_index: None,
-                                        pos: (x / 3, y)
-                                    });
-                                }
-                            }
-                        },
-                        3 => {
-                            let mut scanlines: Vec<Scanline> = inflated_bytes.chunks((out.width as usize * out.depth as usize / 8) as usize + 1)
-                                                                         .map(|c| Scanline::from_bytes(&c.to_vec(), 4, out.depth))
-                                                                         .collect();
-
-                            for i in 0..scanlines.len() {
-                                out.filters.push(scanlines[i].filter);
-                                let prev_line = if i > 0 { Some(scanlines[i - 1].pixel_bytes.clone()) } else { None };
-
-                                scanlines[i].unfilter(if out.filters[i] == 1 { None } else { prev_line.as_ref() });
-                            }
-                            
-                            for y in 0..(out.height as u32) {
-                                for x in 0..(out.width as u32) {
-                                    let pi = scanlines[y as usize].pixel_bytes[x as usize];
-
-                                    out.pixels.push(Pixel::from_palette_index((x, y), pi as usize, &out.colors));
-                                }
-                            }
-                        },
-                        4 => {
-                            let mut scanlines: Vec<Scanline> = inflated_bytes.chunks((out.width as usize / 4 * out.depth as usize) + 1)
-                                                                             .map(|c| Scanline::from_bytes(&c.to_vec(), 2, out.depth))
-                                                                             .collect();
-
-                            for i in 0..scanlines.len() {
-                                out.filters.push(scanlines[i].filter);
-                                let prev_line = if i > 0 { Some(scanlines[i - 1].pixel_bytes.clone()) } else { None };
-
-                                scanlines[i].unfilter(if out.filters[i] == 1 { None } else { prev_line.as_ref() });
-                            }
-
-                            for y in 0..(out.height as u32) {
-                                for x in (0..(out.width as u32 * 2)).step_by(2) {
-                                    let c: Color = {
-                                        let g = scanlines[y as usize].pixel_bytes[x as usize];
-                                        let a = scanlines[y as usize].pixel_bytes[x as usize + 1];
-
-                                        Color::grayscale(g, a)
-                                    };
-
-                                    out.pixels.push(Pixel {
-                                        color: c,
-                                        palette_index: None,
-                                        pos: (x, y)
-                                    });
-                                }
-                            }
-
-                        },
-                        6 => {
-                            let mut scanlines: Vec<Scanline> = inflated_bytes.chunks((out.width as usize * out.depth as usize / 2) + 1)
-                                                                             .map(|c| Scanline::from_bytes(&c.to_vec(), 4, out.depth))
-                                                                             .collect();
-
-                            for i in 0..scanlines.len() {
-                                out.filters.push(scanlines[i].filter);
-                                let prev_line = if i > 0 { Some(scanlines[i - 1].pixel_bytes.clone()) } else { None };
-
-                                scanlines[i].unfilter(if out.filters[i] == 1 { None } else { prev_line.as_ref() });
-                            }
-                            
-                            for y in 0..(out.height as u32) {
-                                for x in (0..(out.width * 4 as u32)).step_by(4) {
-                                    let c: Color = {
-                                        let r = scanlines[y as usize].pixel_bytes[x as usize];
-                                        let g = scanlines[y as usize].pixel_bytes[x as usize + 1];
-                                        let b = scanlines[y as usize].pixel_bytes[x as usize + 2];
-                                        let a = scanlines[y as usize].pixel_bytes[x as usize + 3];
-
-                                        Color { r, g, b, a }
-                                    };
-
-                                    out.pixels.push(Pixel {
-                                        color: c,
-                                        palette_index: None,
-                                        pos: (x / 4, y)
-                                    });
-                                }
-                            }
-                        },
-                        _ => {}
+                }
+            }
+        }
+    }
+
+    // places the pixels of an unfiltered row at their positions in the image, keeping the raw palette indices if asked to;
+    // gray and truecolor rows get alpha from the tRNS color key when the buffer has an alpha channel
+    pub fn write_row(&self, pixels: &mut ImageBuffer, mut indices: Option<&mut ImageBuffer<u8>>, row: &Row) -> Result<(), PngError> {
+        let channels = self.channels();
+
+        // padding bits at the end of the row are dropped by only reading pass.width pixels
+        let samples: Vec<u16> = if self.color_type == 3 { row.scanline.indices().into_iter().map(u16::from).collect() } else { row.scanline.samples() };
+
+        for px in 0..row.pass.width {
+            let (x, y) = row.position(px);
+            let s = &samples[(px as usize * channels)..((px as usize + 1) * channels)];
+
+            if self.color_type == 3 {
+                pixels.put_pixel(x, y, Color16::from_8bit(self.palette_color(s[0] as u8)?));
+
+                if let Some(indices) = indices.as_deref_mut() {
+                    indices.pixel_samples_mut(x, y)[0] = s[0] as u8;
+                }
+            } else if pixels.channels() > channels {
+                let transparent = self.is_color_key(s);
+                let p = pixels.pixel_samples_mut(x, y);
+
+                p[..channels].copy_from_slice(s);
+                p[channels] = if transparent { 0 } else { 0xFFFF };
+            } else {
+                pixels.pixel_samples_mut(x, y).copy_from_slice(s);
+            }
+        }
+
+        Ok(())
+    }
+
+    // keys are compared at the source bit depth, so 16-bit keys must match exactly [src: http://libpng.org/pub/png/spec/1.2/PNG-Chunks.html#C.tRNS]
+    pub fn is_color_key(&self, samples: &[u16]) -> bool {
+        match (self.color_type, self.gray_lvl, self.truecolor_alpha) {
+            (0, Some(g), _) => self.reduce_sample(samples[0]) == g,
+            (2, _, Some(c)) => [c.r, c.g, c.b] == [self.reduce_sample(samples[0]), self.reduce_sample(samples[1]), self.reduce_sample(samples[2])],
+            _ => false
+        }
+    }
+
+    // undoes the scaling to 0-65535 done when decoding
+    pub fn reduce_sample(&self, sample: u16) -> u16 {
+        sample >> (16 - self.depth as u32)
+    }
+
+    // the inverse of reduce_sample; lower depths are scaled by repeating their bits, as the decoded samples are
+    pub fn expand_sample(&self, sample: u16) -> u16 {
+        match self.depth {
+            16 => sample,
+            depth => sample * (0xFFFF / ((1 << depth) - 1))
+        }
+    }
+
+    // an index past the end of the palette is an error [src: http://libpng.org/pub/png/spec/1.2/PNG-Chunks.html#C.PLTE]
+    pub fn palette_color(&self, index: u8) -> Result<&Color, PngError> {
+        self.colors.get(index as usize).ok_or(PngError::PaletteIndexOutOfRange { index, entries: self.colors.len() })
+    }
+
+    // applies an ancillary or header chunk; IDAT is handled by the caller, which also decides what a broken ancillary chunk means
+    pub(crate) fn read_chunk(&mut self, chunk: &Chunk) -> Result<(), PngError> {
+        match &chunk.name[..] {
+            "IHDR" => {
+                if chunk.data.len() != 13 {
+                    return Err(PngError::InvalidIhdr(format!("expected 13 bytes, found {}", chunk.data.len())))
+                }
+
+                // {width(4), height(4), depth(1), color_type(1), compression(1), filter(1), interface(1)} [src: https://en.wikipedia.org/wiki/Portable_Network_Graphics#Critical_chunks]
+                self.width = u32::from_be_bytes(chunk.data[0..4].try_into().unwrap());
+
+                self.height = u32::from_be_bytes(chunk.data[4..8].try_into().unwrap());
+
+                self.depth = chunk.data[8];
+                self.color_type = chunk.data[9];
+                self.compression_type = chunk.data[10];
+                self.filter = chunk.data[11];
+                self.interface = chunk.data[12];
+
+                self.validate_ihdr()?;
+
+                self.aspect_ratio = (self.width as f32) / (self.height as f32);
+            },
+            "PLTE" => {
+                if chunk.data.is_empty() || !chunk.data.len().is_multiple_of(3) {
+                    return Err(PngError::malformed("PLTE", "length is not a multiple of 3"))
+                }
+
+                // at most 2^depth entries for paletted images, and never more than 256 [src: http://libpng.org/pub/png/spec/1.2/PNG-Chunks.html#C.PLTE]
+                let entries = chunk.data.len() / 3;
+                let max_entries = if self.color_type == 3 { 1 << self.depth } else { 256 };
+
+                if entries > max_entries {
+                    return Err(PngError::malformed("PLTE", &format!("{} entries do not fit a {}-bit image", entries, self.depth)))
+                }
+
+                // entries are kept exactly as stored; duplicates are still distinct indices
+                self.colors = chunk.data.chunks(3).map(|c| Color { r: c[0], g: c[1], b: c[2], a: 255 }).collect();
+            },
+            "gAMA" => {
+                if chunk.data.len() != 4 {
+                    return Err(PngError::malformed("gAMA", "expected 4 bytes"))
+                }
+
+                self.gamma = u32::from_be_bytes(chunk.data[..].try_into().unwrap());
+            },
+            "sRGB" => {
+                if chunk.data.len() != 1 {
+                    return Err(PngError::malformed("sRGB", "expected 1 byte"))
+                }
+
+                self.rendering_intent = chunk.data[0];
+            },
+            "cHRM" => {
+                self.chromaticities = Some(Chromaticities::from_chrm(&chunk.data)?);
+            },
+            "iCCP" => {
+                self.icc_profile = Some(IccProfile::from_iccp(&chunk.data)?);
+            },
+            "pHYs" => {
+                if chunk.data.len() != 9 {
+                    return Err(PngError::malformed("pHYs", "expected 9 bytes"))
+                }
+
+                self.ppu_x = u32::from_be_bytes(chunk.data[0..4].try_into().unwrap());
+
+                self.ppu_y = u32::from_be_bytes(chunk.data[4..8].try_into().unwrap());
+
+                self.unit_spec = chunk.data[8];
+            },
+            "tRNS" => {
+                match self.color_type {
+                    0 => {
+                        if chunk.data.len() != 2 {
+                            return Err(PngError::malformed("tRNS", "expected 2 bytes for grayscale"))
+                        }
+
+                        self.gray_lvl = Some(u16::from_be_bytes(chunk.data[..].try_into().unwrap()));
+                    },
+                    2 => {
+                        if chunk.data.len() != 6 {
+                            return Err(PngError::malformed("tRNS", "expected 6 bytes for truecolor"))
+                        }
+
+                        let r = u16::from_be_bytes(chunk.data[0..2].try_into().unwrap());
+                        let g = u16::from_be_bytes(chunk.data[2..4].try_into().unwrap());
+                        let b = u16::from_be_bytes(chunk.data[4..6].try_into().unwrap());
+
+                        let c: Color16 = Color16 { r, g, b, a: 0 };
+
+                        self.truecolor_alpha = Some(c);
+                    },
+                    3 => {
+                        // len(tRNS <= PLTE) [src: http://libpng.org/pub/png/spec/1.2/PNG-Chunks.html#C.tRNS]
+                        if chunk.data.len() > self.colors.len() {
+                            return Err(PngError::malformed("tRNS", &format!("{} alpha values for {} palette entries", chunk.data.len(), self.colors.len())))
+                        }
+
+                        for (color, &alpha) in self.colors.iter_mut().zip(chunk.data.iter()) {
+                            color.a = alpha;
+                        }
+                    },
+                    _ => {}
+                }
+            },
+            "bKGD" => {
+                let background = Background::from_bkgd(self.color_type, self.depth, &chunk.data)?;
+
+                if let Background::PaletteIndex(index) = background {
+                    if index as usize >= self.colors.len() {
+                        return Err(PngError::malformed("bKGD", &format!("palette index {} is out of range for {} entries", index, self.colors.len())))
                     }
-                },
-                _ => {}
+                }
+
+                self.background = Some(background);
+            },
+            "tIME" => {
+                self.modified = Some(Timestamp::from_time(&chunk.data)?);
+            },
+            "sBIT" => {
+                self.significant_bits = Some(SignificantBits::from_sbit(self.color_type, self.depth, &chunk.data)?);
+            },
+            "hIST" => {
+                self.histogram = Some(metadata::histogram_from_hist(&chunk.data, self.colors.len())?);
+            },
+            "sPLT" => {
+                let palette = SuggestedPalette::from_splt(&chunk.data)?;
+
+                // names must be unique within a file
+                if self.suggested_palettes.iter().any(|p| p.name == palette.name) {
+                    return Err(PngError::malformed("sPLT", &format!("duplicate palette name {:?}", palette.name)))
+                }
+
+                self.suggested_palettes.push(palette);
+            },
+            "sTER" => {
+                self.stereo = Some(StereoLayout::from_ster(&chunk.data)?);
+            },
+            // acTL has to come before IDAT [src: https://wiki.mozilla.org/APNG_Specification]
+            "acTL" if !self.chunks.iter().any(|c| c.name == "IDAT") => {
+                match AnimationControl::from_actl(&chunk.data) {
+                    Ok(animation) => self.animation = Some(animation),
+                    Err(e) => self.drop_animation(e)
+                }
+            },
+            "fcTL" if self.animation.is_some() => {
+                if let Err(e) = self.read_fctl(&chunk.data) {
+                    self.drop_animation(e);
+                }
+            },
+            "fdAT" if self.animation.is_some() => {
+                if let Err(e) = self.read_fdat(&chunk.data) {
+                    self.drop_animation(e);
+                }
+            },
+            "eXIf" => {
+                self.exif = Some(Exif::from_exif(&chunk.data)?);
+            },
+            "tEXt" | "zTXt" | "iTXt" => {
+                self.text_chunks.push(TextChunk::from_chunk(&chunk.name, &chunk.data)?);
+            },
+            "IDAT" | "IEND" => {},
+            // a decoder must not go on past a critical chunk it does not know [src: http://libpng.org/pub/png/spec/1.2/PNG-Structure.html#Chunk-naming-conventions]
+            name if chunk.is_critical() => return Err(PngError::UnknownCriticalChunk(name.to_owned())),
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    // the most memory decoded or rendered pixels may take
+    pub fn max_image_bytes(&self) -> usize {
+        self.max_image_bytes.unwrap_or(DEFAULT_MAX_IMAGE_BYTES)
+    }
+
+    // every text entry, decompressed; keywords may repeat [src: http://libpng.org/pub/png/spec/1.2/PNG-Chunks.html#C.Anc-text]
+    pub fn text(&self) -> &[TextChunk] {
+        &self.text_chunks
+    }
+
+    // {ppu_x(4), ppu_y(4), unit(1)}; unit 1 is the meter, 0 leaves only the ratio known [src: http://libpng.org/pub/png/spec/1.2/PNG-Chunks.html#C.pHYs]
+    pub fn dpi(&self) -> Option<(f64, f64)> {
+        match self.unit_spec {
+            1 if self.ppu_x != 0 && self.ppu_y != 0 => Some((self.ppu_x as f64 * 0.0254, self.ppu_y as f64 * 0.0254)),
+            _ => None
+        }
+    }
+
+    // width of a pixel over its height; 1 without pHYs
+    pub fn pixel_aspect(&self) -> f64 {
+        match (self.ppu_x, self.ppu_y) {
+            (0, _) | (_, 0) => 1.0,
+            (x, y) => y as f64 / x as f64
+        }
+    }
+
+    // aspect_ratio as the image should appear, with non-square pixels taken into account
+    pub fn display_aspect_ratio(&self) -> f32 {
+        self.aspect_ratio * self.pixel_aspect() as f32
+    }
+
+    pub fn channels(&self) -> usize {
+        channel_count(self.color_type)
+    }
+
+    // stride of a row of the given width, e.g. the width of an interlace pass
+    pub fn stride(&self, width: u32) -> Stride {
+        Stride::new(self.color_type, self.depth, width)
+    }
+
+    // [src: http://libpng.org/pub/png/spec/1.2/PNG-Chunks.html#C.IHDR]
+    pub fn validate_ihdr(&self) -> Result<(), PngError> {
+        if self.width == 0 || self.height == 0 || self.width > i32::MAX as u32 || self.height > i32::MAX as u32 {
+            return Err(PngError::InvalidIhdr(format!("invalid dimensions {}x{}", self.width, self.height)))
+        }
+
+        let allowed_depths: &[u8] = match self.color_type {
+            0 => &[1, 2, 4, 8, 16],
+            2 | 4 | 6 => &[8, 16],
+            3 => &[1, 2, 4, 8],
+            t => return Err(PngError::UnsupportedColorType(t))
+        };
+
+        if !allowed_depths.contains(&self.depth) {
+            return Err(PngError::InvalidIhdr(format!("bit depth {} is not allowed for color type {}", self.depth, self.color_type)))
+        }
+        if self.compression_type != 0 {
+            return Err(PngError::InvalidIhdr(format!("unknown compression method {}", self.compression_type)))
+        }
+        if self.filter != 0 {
+            return Err(PngError::InvalidIhdr(format!("unknown filter method {}", self.filter)))
+        }
+        if self.interface > 1 {
+            return Err(PngError::InvalidIhdr(format!("unknown interlace method {}", self.interface)))
+        }
+
+        Ok(())
+    }
+
+    // other pixels with this image's header and what is needed to show them: color information, background and pixel size,
+    // e.g. for one APNG frame; frames, text, EXIF and the raw chunks other than sRGB are left out
+    pub fn with_pixels(&self, pixels: ImageBuffer) -> Png {
+        Png {
+            chunks: self.chunks.iter().filter(|c| c.name == "sRGB").cloned().collect(),
+            width: pixels.width(),
+            height: pixels.height(),
+            depth: self.depth,
+            color_type: self.color_type,
+            compression_type: self.compression_type,
+            filter: self.filter,
+            interface: self.interface,
+            colors: self.colors.clone(),
+            gamma: self.gamma,
+            rendering_intent: self.rendering_intent,
+            chromaticities: self.chromaticities,
+            icc_profile: self.icc_profile.clone(),
+            ppu_x: self.ppu_x,
+            ppu_y: self.ppu_y,
+            unit_spec: self.unit_spec,
+            gray_lvl: self.gray_lvl,
+            truecolor_alpha: self.truecolor_alpha,
+            background: self.background,
+            significant_bits: self.significant_bits.clone(),
+            aspect_ratio: pixels.width() as f32 / pixels.height() as f32,
+            max_image_bytes: self.max_image_bytes,
+            pixels,
+            ..Png::default()
+        }
+    }
+
+    // color type follows the buffer layout; samples are stored at the given bit depth when encoding
+    pub fn from_image_buffer(pixels: ImageBuffer, depth: u8) -> Png {
+        let color_type = match pixels.layout() {
+            ChannelLayout::Gray => 0,
+            ChannelLayout::Rgb => 2,
+            ChannelLayout::GrayAlpha => 4,
+            ChannelLayout::Rgba => 6
+        };
+
+        Png {
+            width: pixels.width(),
+            height: pixels.height(),
+            depth,
+            color_type,
+            aspect_ratio: pixels.width() as f32 / pixels.height() as f32,
+            pixels,
+            ..Png::default()
+        }
+    }
+
+    // the uncompressed ICC profile, for callers that manage color themselves
+    pub fn icc_profile_bytes(&self) -> Option<&[u8]> {
+        self.icc_profile.as_ref().map(|p| &p.bytes[..])
+    }
+
+    // PLTE colors, with alpha from tRNS
+    pub fn palette(&self) -> &[Color] {
+        &self.colors
+    }
+
+    // swaps in a new palette, recoloring every pixel from its index; fails if an index does not fit the new palette
+    pub fn set_palette(&mut self, palette: Vec<Color>) -> Result<(), PngError> {
+        if let Some(indices) = &self.indices {
+            if let Some(&index) = indices.as_raw().iter().find(|&&i| i as usize >= palette.len()) {
+                return Err(PngError::PaletteIndexOutOfRange { index, entries: palette.len() })
             }
         }
 
+        self.colors = palette;
+
+        if let Some(indices) = &self.indices {
+            for y in 0..indices.height() {
+                for x in 0..indices.width() {
+                    let i = indices.pixel_samples(x, y)[0] as usize;
+
+                    self.pixels.put_pixel(x, y, Color16::from_8bit(&self.colors[i]));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // like pixels.pixels(), with the palette index filled in for paletted images
+    pub fn indexed_pixels(&self) -> impl Iterator<Item = Pixel> + '_ {
+        self.pixels.pixels().map(move |p| match &self.indices {
+            Some(indices) => Pixel::from_palette_index(p.pos, indices.pixel_samples(p.pos.0, p.pos.1)[0] as usize, &self.colors),
+            None => p
+        })
+    }
+
+    // a paletted image from an index per pixel; depth is the bit depth the indices are stored at when encoding
+    pub fn from_indexed(indices: ImageBuffer<u8>, palette: Vec<Color>, depth: u8) -> Result<Png, PngError> {
+        let mut out = Png {
+            width: indices.width(),
+            height: indices.height(),
+            depth,
+            color_type: 3,
+            aspect_ratio: indices.width() as f32 / indices.height() as f32,
+            pixels: ImageBuffer::new(indices.width(), indices.height(), ChannelLayout::Rgba),
+            indices: Some(indices),
+            ..Png::default()
+        };
+        out.set_palette(palette)?;
+
         Ok(out)
     }
 
-    pub fn scale(&mut self, scalar: usize) {
-        let mut new_pixels: Vec<Pixel> = Vec::new();
-        
-        for y in 0..(self.height * scalar as u32) {
-            let iy = (y as f32 / scalar as f32).floor() as usize;
+    pub fn scale(&mut self, scalar: usize) -> Result<(), PngError> {
+        let pixels = self.pixels.scale(scalar as u32, self.max_image_bytes())?;
+        let budget = self.max_image_bytes() - pixels.as_raw().len() * 2;
+        self.indices = self.indices.as_ref().map(|i| i.scale(scalar as u32, budget)).transpose()?;
 
-            for x in 0..(self.width * scalar as u32) {
-                let ix = (x as f32 / scalar as f32).floor() as usize;
+        self.width = pixels.width();
+        self.height = pixels.height();
+        self.pixels = pixels;
 
-                new_pixels.push(self.pixels[ix+iy*self.width as usize]);
-            }
+        Ok(())
+    }
+
+    // the size correct_pixel_aspect stretches the image to, or None if that does not fit a u32
+    pub fn square_pixel_size(&self) -> Option<(u32, u32)> {
+        let aspect = self.pixel_aspect();
+
+        let (width, height) = match aspect > 1.0 {
+            true => ((self.width as f64 * aspect).round(), self.height as f64),
+            false => (self.width as f64, (self.height as f64 / aspect).round())
+        };
+
+        match width <= u32::MAX as f64 && height <= u32::MAX as f64 {
+            true => Some((width as u32, height as u32)),
+            false => None
+        }
+    }
+
+    // stretches the image so its pixels are square, keeping the larger pixel dimension; the result has equal ppu_x and ppu_y
+    pub fn correct_pixel_aspect(&mut self) -> Result<(), PngError> {
+        if self.pixel_aspect() == 1.0 {
+            return Ok(())
         }
 
-        self.pixels = new_pixels;
+        let limit = self.max_image_bytes();
+        let (width, height) = self.square_pixel_size().ok_or(PngError::ImageTooLarge { width: self.width, height: self.height, limit })?;
 
-        self.width *= scalar as u32;
-        self.height *= scalar as u32;
+        let pixels = self.pixels.resize(width, height, limit)?;
+        let budget = limit - pixels.as_raw().len() * 2;
+        self.indices = self.indices.as_ref().map(|i| i.resize(width, height, budget)).transpose()?;
+        self.pixels = pixels;
+
+        self.width = width;
+        self.height = height;
+        self.aspect_ratio = width as f32 / height as f32;
+
+        let ppu = max(self.ppu_x, self.ppu_y);
+        self.ppu_x = ppu;
+        self.ppu_y = ppu;
+
+        Ok(())
     }
 
-    pub fn scale_to_min_width(&mut self, min_width: u32) {
-        if max(self.width, min_width) == min_width {
-            let s = (min_width as f32 / self.width as f32).floor() as usize;
+    // the integer factor scale_to_min_width scales an image of this width by
+    pub fn min_width_scale(width: u32, min_width: u32) -> u32 {
+        match width {
+            0 => 1,
+            w => max(min_width / w, 1)
+        }
+    }
 
-            self.scale(s);
+    pub fn scale_to_min_width(&mut self, min_width: u32) -> Result<(), PngError> {
+        match Png::min_width_scale(self.width, min_width) {
+            1 => Ok(()),
+            s => self.scale(s as usize)
         }
     }
 
     pub fn print(&self, full: bool) {
-        println!("Reading PNG from: {}", self.filepath);
-        println!("Size:             {} bytes", self.raw_bytes.len());
+        println!("Reading PNG from: {}", self.filepath.as_deref().unwrap_or("(memory)"));
+        println!("Size:             {} bytes", self.size);
         println!("Chunks: {{");
 
         println!("  IHDR: {{");
@@ -437,7 +725,7 @@ impl Png {
         for i in 0..self.colors.len() {
             println!("      [{:03}]: {}", i, self.colors[i].as_hex());
         }
-        if self.colors.len() == 0 {
+        if self.colors.is_empty() {
             println!("      None");
         }
         println!("    }}");
@@ -451,15 +739,112 @@ impl Png {
         println!("    rendering_intent: {}", self.rendering_intent);
         println!("  }},");
 
-        println!("  pHYS: {{");
+        println!("  cHRM: {{");
+        match &self.chromaticities {
+            Some(c) => {
+                println!("    white: ({:.5}, {:.5})", c.white.0, c.white.1);
+                println!("    red:   ({:.5}, {:.5})", c.red.0, c.red.1);
+                println!("    green: ({:.5}, {:.5})", c.green.0, c.green.1);
+                println!("    blue:  ({:.5}, {:.5})", c.blue.0, c.blue.1);
+            },
+            None => println!("    None")
+        }
+        println!("  }},");
+
+        println!("  iCCP: {{");
+        match &self.icc_profile {
+            Some(p) => {
+                println!("    name: {}", p.name);
+                println!("    size: {} bytes", p.bytes.len());
+            },
+            None => println!("    None")
+        }
+        println!("  }},");
+
+        println!("  pHYs: {{");
         println!("    ppu_x:     {}", self.ppu_x);
         println!("    ppu_y:     {}", self.ppu_y);
         println!("    unit_spec: {}", self.unit_spec);
+        if let Some((x, y)) = self.dpi() {
+            println!("    dpi:       {:.2} x {:.2}", x, y);
+        }
+        println!("    pixel_aspect: {:.4}", self.pixel_aspect());
         println!("  }},");
         
         println!("  tRNS: {{");
-        println!("    gray_lvl:        {}", if self.gray_lvl == None { "None".to_owned() } else { format!("{}", self.gray_lvl.unwrap()) });
-        println!("    truecolor_alpha: {}", if self.truecolor_alpha == None { "None".to_owned() } else { format!("{}", self.truecolor_alpha.clone().unwrap().as_hex()) });
+        println!("    gray_lvl:        {}", match self.gray_lvl { Some(g) => format!("{}", g), None => "None".to_owned() });
+        println!("    truecolor_alpha: {}", match self.truecolor_alpha { Some(c) => format!("({}, {}, {})", c.r, c.g, c.b), None => "None".to_owned() });
+        println!("  }},");
+
+        println!("  bKGD: {{");
+        println!("    color: {}", match self.background_color() { Some(c) => c.as_hex(), None => "None".to_owned() });
+        println!("  }},");
+
+        println!("  sBIT: {{");
+        println!("    bits: {}", match &self.significant_bits { Some(s) => format!("{:?}", s.bits), None => "None".to_owned() });
+        println!("  }},");
+
+        println!("  hIST: {{");
+        match &self.histogram {
+            Some(h) => h.iter().enumerate().for_each(|(i, f)| println!("    [{:03}]: {}", i, f)),
+            None => println!("    None")
+        }
+        println!("  }},");
+
+        println!("  sPLT: {{");
+        for p in &self.suggested_palettes {
+            println!("    {}: {} entries, {}-bit", p.name, p.entries.len(), p.depth);
+        }
+        if self.suggested_palettes.is_empty() {
+            println!("    None");
+        }
+        println!("  }},");
+
+        println!("  sTER: {{");
+        println!("    layout: {}", match self.stereo { Some(s) => format!("{:?}", s), None => "None".to_owned() });
+        println!("  }},");
+
+        println!("  tIME: {{");
+        println!("    modified: {}", match self.modified { Some(t) => t.to_string(), None => "None".to_owned() });
+        println!("  }},");
+
+        println!("  eXIf: {{");
+        match &self.exif {
+            Some(e) => {
+                println!("    orientation: {}", match e.orientation() { Some(o) => format!("{:?}", o), None => "None".to_owned() });
+                println!("    date_time:   {}", e.date_time_original().unwrap_or("None"));
+                println!("    camera:      {}", match (e.make(), e.model()) { (None, None) => "None".to_owned(), (make, model) => format!("{} {}", make.unwrap_or(""), model.unwrap_or("")).trim().to_owned() });
+                println!("    gps:         {}", match e.gps() { Some(g) => format!("{:.6}, {:.6}", g.latitude, g.longitude), None => "None".to_owned() });
+                println!("    entries:     {}", e.entries.len());
+            },
+            None => println!("    None")
+        }
+        println!("  }},");
+
+        println!("  acTL: {{");
+        match &self.animation {
+            Some(a) => {
+                println!("    num_frames: {}", a.num_frames);
+                println!("    num_plays:  {}", if a.num_plays == 0 { "forever".to_owned() } else { a.num_plays.to_string() });
+                for (i, f) in self.frames.iter().enumerate() {
+                    let c = f.control;
+                    println!("    [{:03}]: {}x{} at ({}, {}), {} ms, {:?}, {:?}", i, c.width, c.height, c.x_offset, c.y_offset, c.delay().as_millis(), c.dispose_op, c.blend_op);
+                }
+            },
+            None => println!("    None")
+        }
+        println!("  }},");
+
+        println!("  tEXt/zTXt/iTXt: {{");
+        for t in &self.text_chunks {
+            match (&t.language, &t.translated_keyword) {
+                (Some(language), Some(translated)) if !language.is_empty() || !translated.is_empty() => println!("    {} ({}, {}): {:?}", t.keyword, language, translated, t.text),
+                _ => println!("    {}: {:?}", t.keyword, t.text)
+            }
+        }
+        if self.text_chunks.is_empty() {
+            println!("    None");
+        }
         println!("  }},");
 
         println!("  IDAT: {{");
@@ -469,12 +854,10 @@ impl Png {
             print!("{}: ", self.filters[y as usize]);
 
             for x in 0..min(self.width, 8) {
-                let p = &self.pixels[(x+y*self.width) as usize];
-
-                print!("[{}] ", p.color.as_hex())
+                print!("[{}] ", self.pixels.get_pixel(x, y).as_hex())
             }
             if self.width > 8 { print!("... (+{})", self.width - 8) }
-            println!("");
+            println!();
         }
         if self.height > 8 { println!("      ... (+{})", self.height - 16) }
         println!("    }}");
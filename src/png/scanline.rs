@@ -1,4 +1,54 @@
-use super::helper::{bytes_as_16bit, bytes_as_1bit, bytes_as_2bit, bytes_as_4bit, bytes_from_16bit_to_8bit};
+use deflate::{deflate_bytes_conf, Compression};
+use super::error::PngError;
+use super::helper::{bytes_as_16bit, bytes_as_1bit, bytes_as_2bit, bytes_as_4bit, bytes_as_indices};
+
+// how the encoder picks a filter type for each scanline
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FilterStrategy {
+    // the same filter type for every line
+    Fixed(u8),
+    // smallest sum of absolute differences, treating filtered bytes as signed [src: http://libpng.org/pub/png/spec/1.2/PNG-Encoders.html#E.Filter-selection]
+    MinSum,
+    // compress the line with every filter type and keep the smallest
+    BruteForce,
+    // lowest Shannon entropy of the filtered bytes
+    Entropy
+}
+
+// samples per pixel for a color type [src: http://libpng.org/pub/png/spec/1.2/PNG-Chunks.html#C.IHDR]
+pub fn channel_count(color_type: u8) -> usize {
+    match color_type {
+        2 => 3,
+        4 => 2,
+        6 => 4,
+        _ => 1
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Stride {
+    // pixel bytes in a row, excluding the filter byte; sub-byte rows are padded to a whole byte
+    pub row_length: usize,
+
+    // how far back filters look for the corresponding byte of the previous pixel (1 for sub-byte pixels) [src: http://libpng.org/pub/png/spec/1.2/PNG-Filters.html]
+    pub pixel_length: usize
+}
+
+impl Stride {
+    pub fn new(color_type: u8, depth: u8, width: u32) -> Stride {
+        let bits_per_pixel = channel_count(color_type) * depth as usize;
+
+        Stride {
+            row_length: (width as usize * bits_per_pixel).div_ceil(8),
+            pixel_length: (bits_per_pixel / 8).max(1)
+        }
+    }
+
+    // size of the row in the image data, including the filter byte
+    pub fn line_size(&self) -> usize {
+        self.row_length + 1
+    }
+}
 
 #[derive(Clone)]
 pub struct Scanline {
@@ -10,37 +60,167 @@ pub struct Scanline {
 }
 
 impl Scanline {
-    pub fn from_bytes(bytes: &Vec<u8>, pixel_length: u8, bit_depth: u8) -> Scanline {
-        let pbytes: Vec<u8> = bytes[1..].to_vec(); 
-        let mut scanline = Scanline {
+    // bytes are {filter(1), filtered pixel bytes}; samples are only unpacked after unfiltering
+    pub fn from_bytes(bytes: &[u8], pixel_length: u8, bit_depth: u8) -> Scanline {
+        Scanline {
             pixel_length,
-            pixel_bytes: pbytes,
+            pixel_bytes: bytes[1..].to_vec(),
             filter: bytes[0],
             depth: bit_depth,
-            raw_bytes: bytes.clone()
-        };
+            raw_bytes: bytes.to_vec()
+        }
+    }
+
+    // unfiltered pixel bytes, as produced by the encoder
+    pub fn from_pixel_bytes(pixel_bytes: Vec<u8>, pixel_length: u8, bit_depth: u8) -> Scanline {
+        let mut raw_bytes: Vec<u8> = vec![0];
+        raw_bytes.extend_from_slice(&pixel_bytes);
 
-        match bit_depth {
-            1  => { scanline.pixel_bytes = bytes_as_1bit(&scanline.pixel_bytes) },
-            2  => { scanline.pixel_bytes = bytes_as_2bit(&scanline.pixel_bytes) },
-            4  => { scanline.pixel_bytes = bytes_as_4bit(&scanline.pixel_bytes) },
-            8  => { /* Bits are 8bit by default */ },
-            16 => { scanline.pixel_bytes = bytes_from_16bit_to_8bit(&bytes_as_16bit(&scanline.pixel_bytes)) },
-            _  => {}
+        Scanline {
+            pixel_length,
+            pixel_bytes,
+            filter: 0,
+            depth: bit_depth,
+            raw_bytes
         }
+    }
+
+    // one 16-bit sample per entry, with lower bit depths scaled up to 0-65535
+    pub fn samples(&self) -> Vec<u16> {
+        let samples: Vec<u8> = match self.depth {
+            1  => bytes_as_1bit(&self.pixel_bytes),
+            2  => bytes_as_2bit(&self.pixel_bytes),
+            4  => bytes_as_4bit(&self.pixel_bytes),
+            16 => { return bytes_as_16bit(&self.pixel_bytes) },
+            _  => self.pixel_bytes.clone()
+        };
 
-        scanline
+        samples.iter().map(|&s| s as u16 * 0x101).collect()
     }
 
-    pub fn unfilter(&mut self, previous_line: Option<&Vec<u8>>) {
+    // one palette index per entry
+    pub fn indices(&self) -> Vec<u8> {
+        bytes_as_indices(&self.pixel_bytes, self.depth)
+    }
+
+    pub fn unfilter(&mut self, previous_line: Option<&[u8]>) -> Result<(), PngError> {
+        // the line above the first scanline is treated as all zeros [src: http://libpng.org/pub/png/spec/1.2/PNG-Filters.html]
+        let zeros: Vec<u8> = vec![0; self.pixel_bytes.len()];
+        let previous_line = previous_line.unwrap_or(&zeros);
+
         match self.filter {
             0 => {},
             1 => { self.unsub() },
-            2 => { self.unup(previous_line.expect("No previous line given for the sub algorithm; aborting")) },
-            3 => { self.unaverage(previous_line.expect("No previous line given for the average algorithm; aborting")) },
-            4 => { self.unpaeth(previous_line.expect("No previous line given for the paeth algorithm; aborting")) },
-            _ => { println!("unrecognized filter type: {}", self.filter) }
+            2 => { self.unup(previous_line) },
+            3 => { self.unaverage(previous_line) },
+            4 => { self.unpaeth(previous_line) },
+            _ => { return Err(PngError::BadFilterType(self.filter)) }
+        }
+
+        Ok(())
+    }
+
+    // filters the (unfiltered) pixel bytes in place; raw_bytes becomes the {filter(1), filtered bytes} line to write
+    pub fn apply_filter(&mut self, strategy: FilterStrategy, previous_line: Option<&[u8]>) -> Result<(), PngError> {
+        let zeros: Vec<u8> = vec![0; self.pixel_bytes.len()];
+        let previous_line = previous_line.unwrap_or(&zeros);
+
+        let (filter, filtered) = match strategy {
+            FilterStrategy::Fixed(f) => {
+                if f > 4 {
+                    return Err(PngError::BadFilterType(f))
+                }
+
+                (f, self.filtered(f, previous_line))
+            },
+            FilterStrategy::MinSum => self.best_filter(previous_line, |line| {
+                line.iter().map(|&b| (b as i8).unsigned_abs() as f64).sum()
+            }),
+            FilterStrategy::BruteForce => self.best_filter(previous_line, |line| {
+                deflate_bytes_conf(line, Compression::Default).len() as f64
+            }),
+            FilterStrategy::Entropy => self.best_filter(previous_line, entropy)
+        };
+
+        self.filter = filter;
+        self.pixel_bytes = filtered;
+
+        self.raw_bytes = vec![filter];
+        self.raw_bytes.extend_from_slice(&self.pixel_bytes);
+
+        Ok(())
+    }
+
+    fn best_filter<F: Fn(&[u8]) -> f64>(&self, previous_line: &[u8], cost: F) -> (u8, Vec<u8>) {
+        (0..5).map(|f| (f, self.filtered(f, previous_line)))
+              .min_by(|a, b| cost(&a.1).partial_cmp(&cost(&b.1)).unwrap())
+              .unwrap()
+    }
+
+    fn filtered(&self, filter: u8, previous_line: &[u8]) -> Vec<u8> {
+        match filter {
+            1 => self.sub(),
+            2 => self.up(previous_line),
+            3 => self.average(previous_line),
+            4 => self.paeth(previous_line),
+            _ => self.pixel_bytes.clone()
+        }
+    }
+
+    fn sub(&self) -> Vec<u8> {
+        let mut filtered: Vec<u8> = Vec::new();
+
+        for i in 0..self.pixel_bytes.len() {
+            let left = if i < self.pixel_length as usize { 0 } else { self.pixel_bytes[i - self.pixel_length as usize] };
+
+            filtered.push(self.pixel_bytes[i].wrapping_sub(left));
+        }
+
+        filtered
+    }
+
+    #[allow(clippy::needless_range_loop)]
+    fn up(&self, previous_line: &[u8]) -> Vec<u8> {
+        let mut filtered: Vec<u8> = Vec::new();
+
+        for i in 0..self.pixel_bytes.len() {
+            filtered.push(self.pixel_bytes[i].wrapping_sub(previous_line[i]));
+        }
+
+        filtered
+    }
+
+    #[allow(clippy::needless_range_loop)]
+    fn average(&self, previous_line: &[u8]) -> Vec<u8> {
+        let mut filtered: Vec<u8> = Vec::new();
+
+        for i in 0..self.pixel_bytes.len() {
+            let left = if i < self.pixel_length as usize { 0 } else { self.pixel_bytes[i - self.pixel_length as usize] };
+
+            filtered.push(self.pixel_bytes[i].wrapping_sub(((left as usize + previous_line[i] as usize) / 2) as u8));
         }
+
+        filtered
+    }
+
+    fn paeth(&self, previous_line: &[u8]) -> Vec<u8> {
+        let mut filtered: Vec<u8> = Vec::new();
+
+        for i in 0..self.pixel_bytes.len() {
+            let pp = if i < self.pixel_length as usize {
+                Scanline::paeth_predictor(0, previous_line[i] as usize, 0)
+            } else {
+                Scanline::paeth_predictor(
+                    self.pixel_bytes[i - self.pixel_length as usize] as usize, // left
+                    previous_line[i] as usize,                                // up
+                    previous_line[i - self.pixel_length as usize] as usize    // up + left
+                )
+            };
+
+            filtered.push(self.pixel_bytes[i].wrapping_sub(pp as u8));
+        }
+
+        filtered
     }
 
     fn unsub(&mut self) {
@@ -57,7 +237,8 @@ impl Scanline {
         self.pixel_bytes = unfiltered;
     }
 
-    fn unup(&mut self, previous_line: &Vec<u8>) {
+    #[allow(clippy::needless_range_loop)]
+    fn unup(&mut self, previous_line: &[u8]) {
         let mut unfiltered: Vec<u8> = Vec::new();
 
         for i in 0..self.pixel_bytes.len() {
@@ -67,7 +248,7 @@ impl Scanline {
         self.pixel_bytes = unfiltered;
     }
     
-    fn unaverage(&mut self, previous_line: &Vec<u8>) {
+    fn unaverage(&mut self, previous_line: &[u8]) {
         let mut unfiltered: Vec<u8> = Vec::new();
 
         for i in 0..self.pixel_bytes.len() {
@@ -81,7 +262,7 @@ impl Scanline {
         self.pixel_bytes = unfiltered;
     }
 
-    fn unpaeth(&mut self, previous_line: &Vec<u8>) {
+    fn unpaeth(&mut self, previous_line: &[u8]) {
         let mut unfiltered: Vec<u8> = Vec::new();
 
         for i in 0..self.pixel_bytes.len() {
@@ -112,12 +293,29 @@ impl Scanline {
         let c = c as isize; 
 
         let p = a + b - c;
-        let pa = (p as isize - a).abs() as usize;
-        let pb = (p as isize - b).abs() as usize;
-        let pc = (p as isize - c).abs() as usize;
+        let pa = (p - a).unsigned_abs();
+        let pb = (p - b).unsigned_abs();
+        let pc = (p - c).unsigned_abs();
 
-        if pa <= pb && pa <= pc { return a as usize; }
-        else if pb <= pc { return b as usize; }
-        else { return c as usize; }
+        if pa <= pb && pa <= pc { a as usize }
+        else if pb <= pc { b as usize }
+        else { c as usize }
     }
-}
\ No newline at end of file
+}
+
+fn entropy(bytes: &[u8]) -> f64 {
+    let mut counts = [0usize; 256];
+
+    for &b in bytes {
+        counts[b as usize] += 1;
+    }
+
+    counts.iter()
+          .filter(|&&c| c > 0)
+          .map(|&c| {
+              let p = c as f64 / bytes.len() as f64;
+
+              -p * p.log2()
+          })
+          .sum()
+}
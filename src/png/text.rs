@@ -0,0 +1,150 @@
+use super::{error::PngError, zlib};
+
+// zTXt and compressed iTXt text may inflate to at most this many bytes
+pub const MAX_TEXT_BYTES: usize = 4 << 20;
+
+// which chunk a text entry came from, or should be written as
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextKind {
+    // Latin-1, uncompressed
+    Text,
+
+    // Latin-1, zlib compressed
+    Compressed,
+
+    // UTF-8 with a language tag, optionally compressed
+    International { compressed: bool }
+}
+
+// one tEXt, zTXt or iTXt entry [src: http://libpng.org/pub/png/spec/1.2/PNG-Chunks.html#C.Anc-text]
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextChunk {
+    pub kind: TextKind,
+    pub keyword: String,
+    pub text: String,
+
+    // iTXt only; both may be empty
+    pub language: Option<String>,
+    pub translated_keyword: Option<String>
+}
+
+impl TextChunk {
+    // an uncompressed tEXt entry
+    pub fn new(keyword: &str, text: &str) -> TextChunk {
+        TextChunk { kind: TextKind::Text, keyword: keyword.to_owned(), text: text.to_owned(), language: None, translated_keyword: None }
+    }
+
+    pub fn from_chunk(name: &str, data: &[u8]) -> Result<TextChunk, PngError> {
+        let (keyword, rest) = split_keyword(name, data)?;
+
+        match name {
+            // {keyword(1-79), null(1), text}
+            "tEXt" => Ok(TextChunk { kind: TextKind::Text, keyword, text: latin1(rest), language: None, translated_keyword: None }),
+
+            // {keyword(1-79), null(1), compression method(1), zlib stream}
+            "zTXt" => {
+                let (&method, compressed) = rest.split_first().ok_or_else(|| PngError::malformed(name, "missing compression method"))?;
+
+                Ok(TextChunk { kind: TextKind::Compressed, keyword, text: latin1(&inflate(name, method, compressed)?), language: None, translated_keyword: None })
+            },
+
+            // {keyword(1-79), null(1), compression flag(1), compression method(1), language tag, null(1), translated keyword, null(1), text}
+            "iTXt" => {
+                if rest.len() < 2 {
+                    return Err(PngError::malformed(name, "missing compression flag"))
+                }
+
+                let (compressed, method) = (rest[0], rest[1]);
+                let (language, rest) = split_null(name, &rest[2..], "language tag")?;
+                let (translated_keyword, rest) = split_null(name, rest, "translated keyword")?;
+
+                let text = match compressed {
+                    0 => rest.to_vec(),
+                    1 => inflate(name, method, rest)?,
+                    _ => return Err(PngError::malformed(name, "invalid compression flag"))
+                };
+
+                Ok(TextChunk {
+                    kind: TextKind::International { compressed: compressed == 1 },
+                    keyword,
+                    text: utf8(name, text)?,
+                    language: Some(utf8(name, language.to_vec())?),
+                    translated_keyword: Some(utf8(name, translated_keyword.to_vec())?)
+                })
+            },
+            _ => Err(PngError::malformed(name, "not a text chunk"))
+        }
+    }
+
+    pub fn chunk_name(&self) -> &'static str {
+        match self.kind {
+            TextKind::Text => "tEXt",
+            TextKind::Compressed => "zTXt",
+            TextKind::International { .. } => "iTXt"
+        }
+    }
+
+    // characters outside Latin-1 are replaced with '?' for tEXt and zTXt
+    pub fn to_chunk_data(&self) -> Vec<u8> {
+        let mut data: Vec<u8> = to_latin1(&self.keyword);
+        data.push(0);
+
+        match self.kind {
+            TextKind::Text => data.extend(to_latin1(&self.text)),
+            TextKind::Compressed => {
+                data.push(0);
+                data.extend(deflate::deflate_bytes_zlib(&to_latin1(&self.text)));
+            },
+            TextKind::International { compressed } => {
+                data.extend_from_slice(&[compressed as u8, 0]);
+                data.extend_from_slice(self.language.as_deref().unwrap_or("").as_bytes());
+                data.push(0);
+                data.extend_from_slice(self.translated_keyword.as_deref().unwrap_or("").as_bytes());
+                data.push(0);
+
+                match compressed {
+                    true => data.extend(deflate::deflate_bytes_zlib(self.text.as_bytes())),
+                    false => data.extend_from_slice(self.text.as_bytes())
+                }
+            }
+        }
+
+        data
+    }
+}
+
+// keywords are 1-79 Latin-1 characters followed by a null
+fn split_keyword<'a>(name: &str, data: &'a [u8]) -> Result<(String, &'a [u8]), PngError> {
+    match data.iter().position(|&b| b == 0) {
+        Some(i) if (1..=79).contains(&i) => Ok((latin1(&data[..i]), &data[i + 1..])),
+        _ => Err(PngError::malformed(name, "keyword must be 1-79 bytes followed by a null"))
+    }
+}
+
+fn split_null<'a>(name: &str, data: &'a [u8], field: &str) -> Result<(&'a [u8], &'a [u8]), PngError> {
+    match data.iter().position(|&b| b == 0) {
+        Some(i) => Ok((&data[..i], &data[i + 1..])),
+        None => Err(PngError::malformed(name, &format!("{} is not null terminated", field)))
+    }
+}
+
+// method 0 is the only one defined
+fn inflate(name: &str, method: u8, data: &[u8]) -> Result<Vec<u8>, PngError> {
+    if method != 0 {
+        return Err(PngError::malformed(name, "unknown compression method"))
+    }
+
+    zlib::inflate_zlib_limited(data, MAX_TEXT_BYTES).map_err(|e| PngError::malformed(name, &e.to_string()))
+}
+
+fn latin1(bytes: &[u8]) -> String {
+    bytes.iter().map(|&b| b as char).collect()
+}
+
+fn to_latin1(s: &str) -> Vec<u8> {
+    s.chars().map(|c| if (c as u32) < 256 { c as u8 } else { b'?' }).collect()
+}
+
+fn utf8(name: &str, bytes: Vec<u8>) -> Result<String, PngError> {
+    String::from_utf8(bytes).map_err(|_| PngError::malformed(name, "text is not valid UTF-8"))
+}
@@ -0,0 +1,492 @@
+use super::error::PngError;
+
+// [src: https://www.rfc-editor.org/rfc/rfc1951#section-3.2.5]
+const LENGTH_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DIST_BASE: [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577];
+const DIST_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+
+// order of the code length code lengths in a dynamic block header [src: https://www.rfc-editor.org/rfc/rfc1951#section-3.2.7]
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+// back-references reach at most 32K into the output
+const WINDOW_SIZE: usize = 32768;
+
+// [src: https://www.rfc-editor.org/rfc/rfc1950#section-9]
+pub fn adler32(bytes: &[u8]) -> u32 {
+    let mut adler = Adler32::default();
+    adler.update(bytes);
+
+    adler.value()
+}
+
+struct Adler32 {
+    a: u32,
+    b: u32
+}
+
+impl Default for Adler32 {
+    fn default() -> Adler32 {
+        Adler32 { a: 1, b: 0 }
+    }
+}
+
+impl Adler32 {
+    fn update(&mut self, bytes: &[u8]) {
+        // 5552 is the largest n where sums can't overflow a u32 before taking the modulo
+        for chunk in bytes.chunks(5552) {
+            for &byte in chunk {
+                self.a += byte as u32;
+                self.b += self.a;
+            }
+
+            self.a %= 65521;
+            self.b %= 65521;
+        }
+    }
+
+    fn value(&self) -> u32 {
+        (self.b << 16) | self.a
+    }
+}
+
+// inflates a complete zlib stream; one that inflates to more than max_output bytes is an error rather than a few gigabytes.
+// there is no unbounded version, so every caller has to say how large its data may get
+pub fn inflate_zlib_limited(bytes: &[u8], max_output: usize) -> Result<Vec<u8>, PngError> {
+    // decoding pauses past the limit, which leaves the stream unfinished
+    let mut inflater = Inflater::with_output_limit(max_output.saturating_add(1));
+    inflater.push(bytes)?;
+
+    if !inflater.is_finished() && inflater.output.len() - inflater.pending > max_output {
+        return Err(PngError::Inflate(format!("inflated data is larger than {} bytes", max_output)))
+    }
+
+    inflater.finish()
+}
+
+// canonical huffman code, stored as the number of codes per length and the symbols in code order [src: https://www.rfc-editor.org/rfc/rfc1951#section-3.2.2]
+#[derive(Clone, Debug)]
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>
+}
+
+impl Huffman {
+    #[allow(clippy::needless_range_loop)]
+    fn from_lengths(lengths: &[u8]) -> Result<Huffman, PngError> {
+        let mut counts = [0u16; 16];
+        for &l in lengths {
+            counts[l as usize] += 1;
+        }
+        counts[0] = 0;
+
+        // reject over-subscribed codes
+        let mut left: i32 = 1;
+        for len in 1..16 {
+            left = (left << 1) - counts[len] as i32;
+
+            if left < 0 {
+                return Err(PngError::Inflate("over-subscribed huffman code".to_owned()))
+            }
+        }
+
+        let mut offsets = [0u16; 16];
+        for len in 1..15 {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+
+        let mut symbols: Vec<u16> = vec![0; lengths.iter().filter(|&&l| l != 0).count()];
+        for (symbol, &l) in lengths.iter().enumerate() {
+            if l != 0 {
+                symbols[offsets[l as usize] as usize] = symbol as u16;
+                offsets[l as usize] += 1;
+            }
+        }
+
+        Ok(Huffman { counts, symbols })
+    }
+
+    fn fixed() -> (Huffman, Huffman) {
+        // [src: https://www.rfc-editor.org/rfc/rfc1951#section-3.2.6]
+        let mut lengths = [0u8; 288];
+        for (i, l) in lengths.iter_mut().enumerate() {
+            *l = match i {
+                0..=143 => 8,
+                144..=255 => 9,
+                256..=279 => 7,
+                _ => 8
+            };
+        }
+
+        (Huffman::from_lengths(&lengths).unwrap(), Huffman::from_lengths(&[5; 30]).unwrap())
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Step<u16> {
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+
+        for len in 1..16 {
+            code |= reader.bits(1)? as i32;
+
+            let count = self.counts[len] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize])
+            }
+
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+
+        Err(Interrupt::Error(PngError::Inflate("invalid huffman code".to_owned())))
+    }
+}
+
+// decoding stops either on bad data or when the buffered input runs out mid-item
+enum Interrupt {
+    NeedInput,
+    Error(PngError)
+}
+
+type Step<T> = Result<T, Interrupt>;
+
+fn invalid<T>(reason: &str) -> Step<T> {
+    Err(Interrupt::Error(PngError::Inflate(reason.to_owned())))
+}
+
+// reads bits least significant first [src: https://www.rfc-editor.org/rfc/rfc1951#section-3.1.1]
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    bit_position: usize
+}
+
+impl<'a> BitReader<'a> {
+    fn bits(&mut self, count: u8) -> Step<u32> {
+        if self.bit_position + count as usize > self.bytes.len() * 8 {
+            return Err(Interrupt::NeedInput)
+        }
+
+        let mut value: u32 = 0;
+        for i in 0..count {
+            let byte = self.bytes[self.bit_position / 8];
+            let bit = (byte >> (self.bit_position % 8)) & 1;
+
+            value |= (bit as u32) << i;
+            self.bit_position += 1;
+        }
+
+        Ok(value)
+    }
+
+    fn align_to_byte(&mut self) {
+        self.bit_position = self.bit_position.div_ceil(8) * 8;
+    }
+}
+
+#[derive(Clone, Debug)]
+enum State {
+    Header,
+    BlockHeader,
+    Stored { remaining: usize },
+    Compressed { literals: Huffman, distances: Huffman },
+    Checksum,
+    Done
+}
+
+// incremental zlib decoder: data can be pushed as it arrives and decoded bytes taken as they become available
+pub struct Inflater {
+    input: Vec<u8>,
+    bit_position: usize,
+
+    state: State,
+    last_block: bool,
+
+    // decoded bytes; everything before `pending` has already been handed out and is only kept as the back-reference window
+    output: Vec<u8>,
+    pending: usize,
+
+    // decoding pauses once this many bytes are waiting to be taken
+    output_limit: usize,
+
+    adler: Adler32
+}
+
+impl Default for Inflater {
+    fn default() -> Inflater {
+        Inflater::new()
+    }
+}
+
+impl Inflater {
+    pub fn new() -> Inflater {
+        Inflater {
+            input: Vec::new(),
+            bit_position: 0,
+            state: State::Header,
+            last_block: false,
+            output: Vec::new(),
+            pending: 0,
+            output_limit: usize::MAX,
+            adler: Adler32::default()
+        }
+    }
+
+    // bounds the output buffered between calls to take_output; pushing no bytes resumes a paused decode
+    pub fn with_output_limit(output_limit: usize) -> Inflater {
+        Inflater { output_limit, ..Inflater::new() }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        matches!(self.state, State::Done)
+    }
+
+    // decodes as much of the buffered input as possible
+    pub fn push(&mut self, bytes: &[u8]) -> Result<(), PngError> {
+        // drop fully consumed input
+        let consumed = self.bit_position / 8;
+        self.input.drain(..consumed);
+        self.bit_position -= consumed * 8;
+
+        // trailing data after the end of the stream is ignored
+        if !self.is_finished() {
+            self.input.extend_from_slice(bytes);
+        }
+
+        loop {
+            if self.is_finished() || self.is_full() {
+                return Ok(())
+            }
+
+            // a step only moves the read position once it completes
+            match self.step() {
+                Ok(()) => {},
+                Err(Interrupt::NeedInput) => return Ok(()),
+                Err(Interrupt::Error(e)) => return Err(e)
+            }
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        self.output.len() - self.pending >= self.output_limit
+    }
+
+    // hands out the bytes decoded since the last call
+    pub fn take_output(&mut self) -> Vec<u8> {
+        let taken = self.output[self.pending..].to_vec();
+
+        if self.output.len() > WINDOW_SIZE {
+            self.output.drain(..self.output.len() - WINDOW_SIZE);
+        }
+        self.pending = self.output.len();
+
+        taken
+    }
+
+    pub fn finish(mut self) -> Result<Vec<u8>, PngError> {
+        if !self.is_finished() {
+            return Err(PngError::Inflate("unexpected end of zlib stream".to_owned()))
+        }
+
+        Ok(self.take_output())
+    }
+
+    fn step(&mut self) -> Step<()> {
+        let input = std::mem::take(&mut self.input);
+        let mut reader = BitReader { bytes: &input, bit_position: self.bit_position };
+
+        let result = self.step_with(&mut reader);
+
+        if result.is_ok() {
+            self.bit_position = reader.bit_position;
+        }
+        self.input = input;
+
+        result
+    }
+
+    fn step_with(&mut self, reader: &mut BitReader) -> Step<()> {
+        match self.state.clone() {
+            State::Header => {
+                // {CMF(1), FLG(1)} [src: https://www.rfc-editor.org/rfc/rfc1950#section-2.2]
+                let cmf = reader.bits(8)?;
+                let flg = reader.bits(8)?;
+
+                if cmf & 0x0F != 8 || (cmf >> 4) > 7 {
+                    return invalid("unsupported compression method")
+                }
+                if ((cmf << 8) | flg) % 31 != 0 {
+                    return invalid("bad header check bits")
+                }
+                if flg & 0x20 != 0 {
+                    return invalid("preset dictionaries are not supported")
+                }
+
+                self.state = State::BlockHeader;
+            },
+            State::BlockHeader => {
+                if self.last_block {
+                    reader.align_to_byte();
+                    self.state = State::Checksum;
+
+                    return Ok(())
+                }
+
+                let last_block = reader.bits(1)? == 1;
+
+                self.state = match reader.bits(2)? {
+                    0 => {
+                        // {LEN(2), NLEN(2)} after aligning to a byte
+                        reader.align_to_byte();
+
+                        let len = reader.bits(16)?;
+                        let nlen = reader.bits(16)?;
+
+                        if len != !nlen & 0xFFFF {
+                            return invalid("stored block length does not match its complement")
+                        }
+
+                        State::Stored { remaining: len as usize }
+                    },
+                    1 => {
+                        let (literals, distances) = Huffman::fixed();
+
+                        State::Compressed { literals, distances }
+                    },
+                    2 => Inflater::read_dynamic_tables(reader)?,
+                    _ => return invalid("invalid block type")
+                };
+
+                self.last_block = last_block;
+            },
+            State::Stored { remaining } => {
+                let start = reader.bit_position / 8;
+                let room = self.output_limit - (self.output.len() - self.pending);
+                let available = (reader.bytes.len() - start).min(remaining).min(room);
+
+                if available == 0 && remaining > 0 {
+                    return Err(Interrupt::NeedInput)
+                }
+
+                let bytes = &reader.bytes[start..start + available];
+                self.adler.update(bytes);
+                self.output.extend_from_slice(bytes);
+
+                reader.bit_position += available * 8;
+
+                self.state = if remaining == available { State::BlockHeader } else { State::Stored { remaining: remaining - available } };
+            },
+            State::Compressed { literals, distances } => {
+                // decode symbols until the input runs out; each completed symbol is committed
+                loop {
+                    match Inflater::read_symbol(reader, &literals, &distances, &mut self.output)? {
+                        Some(start) => {
+                            let end = self.output.len();
+                            self.adler.update(&self.output[start..end]);
+
+                            self.bit_position = reader.bit_position;
+
+                            if self.is_full() {
+                                return Ok(())
+                            }
+                        },
+                        None => {
+                            self.state = State::BlockHeader;
+                            return Ok(())
+                        }
+                    }
+                }
+            },
+            State::Checksum => {
+                let mut checksum: u32 = 0;
+                for _ in 0..4 {
+                    checksum = (checksum << 8) | reader.bits(8)?;
+                }
+
+                if checksum != self.adler.value() {
+                    return invalid("Adler-32 checksum mismatch")
+                }
+
+                self.state = State::Done;
+            },
+            State::Done => {}
+        }
+
+        Ok(())
+    }
+
+    // returns where the decoded bytes start in the output, or None at the end of the block
+    fn read_symbol(reader: &mut BitReader, literals: &Huffman, distances: &Huffman, output: &mut Vec<u8>) -> Step<Option<usize>> {
+        let start = output.len();
+        let symbol = literals.decode(reader)?;
+
+        match symbol {
+            0..=255 => output.push(symbol as u8),
+            256 => return Ok(None),
+            257..=285 => {
+                let i = (symbol - 257) as usize;
+                let length = LENGTH_BASE[i] as usize + reader.bits(LENGTH_EXTRA[i])? as usize;
+
+                let d = distances.decode(reader)? as usize;
+                if d >= 30 {
+                    return invalid("invalid distance symbol")
+                }
+                let distance = DIST_BASE[d] as usize + reader.bits(DIST_EXTRA[d])? as usize;
+
+                if distance > output.len() {
+                    return invalid("distance reaches before the start of the output")
+                }
+
+                // copies may overlap the bytes being written
+                for _ in 0..length {
+                    output.push(output[output.len() - distance]);
+                }
+            },
+            _ => return invalid("invalid literal/length symbol")
+        }
+
+        Ok(Some(start))
+    }
+
+    // [src: https://www.rfc-editor.org/rfc/rfc1951#section-3.2.7]
+    fn read_dynamic_tables(reader: &mut BitReader) -> Step<State> {
+        let hlit = reader.bits(5)? as usize + 257;
+        let hdist = reader.bits(5)? as usize + 1;
+        let hclen = reader.bits(4)? as usize + 4;
+
+        let mut code_length_lengths = [0u8; 19];
+        for &i in CODE_LENGTH_ORDER.iter().take(hclen) {
+            code_length_lengths[i] = reader.bits(3)? as u8;
+        }
+        let code_lengths = Huffman::from_lengths(&code_length_lengths).map_err(Interrupt::Error)?;
+
+        let mut lengths: Vec<u8> = Vec::with_capacity(hlit + hdist);
+        while lengths.len() < hlit + hdist {
+            let symbol = code_lengths.decode(reader)?;
+
+            let (value, repeat) = match symbol {
+                0..=15 => (symbol as u8, 1),
+                16 => match lengths.last() {
+                    Some(&previous) => (previous, 3 + reader.bits(2)? as usize),
+                    None => return invalid("repeat code with no previous length")
+                },
+                17 => (0, 3 + reader.bits(3)? as usize),
+                _ => (0, 11 + reader.bits(7)? as usize)
+            };
+
+            if lengths.len() + repeat > hlit + hdist {
+                return invalid("code lengths overflow the alphabet")
+            }
+            lengths.extend(std::iter::repeat_n(value, repeat));
+        }
+
+        if lengths[256] == 0 {
+            return invalid("missing end-of-block code")
+        }
+
+        let literals = Huffman::from_lengths(&lengths[..hlit]).map_err(Interrupt::Error)?;
+        let distances = Huffman::from_lengths(&lengths[hlit..]).map_err(Interrupt::Error)?;
+
+        Ok(State::Compressed { literals, distances })
+    }
+}
@@ -0,0 +1,69 @@
+use std::time::{Duration, Instant};
+use glium::{
+    glutin::{self, event::{Event, StartCause, WindowEvent}, event_loop::{ControlFlow, EventLoop}},
+    texture::{MipmapsOption, Texture2d, UncompressedFloatFormat},
+    uniforms::MagnifySamplerFilter,
+    Display, Rect, Surface
+};
+use pixel_canvas::Image;
+
+// about 60 frames per second, like pixel_canvas::Canvas
+const FRAME_TIME: Duration = Duration::from_nanos(16_666_667);
+
+// pixel_canvas::Canvas only sets the title when the window opens; this runs the same loop but lets the render callback retitle the window
+pub struct Window<State> {
+    width: usize,
+    height: usize,
+    title: String,
+    state: State,
+    input: fn(&mut State, &Event<()>) -> bool
+}
+
+impl<State: 'static> Window<State> {
+    pub fn new(width: usize, height: usize, title: String, state: State) -> Window<State> {
+        Window { width, height, title, state, input: |_, _| false }
+    }
+
+    // called with every window event; returns whether the state changed
+    pub fn input(self, input: fn(&mut State, &Event<()>) -> bool) -> Window<State> {
+        Window { input, ..self }
+    }
+
+    // calls render about 60 times a second until the window is closed; the title it returns replaces the current one
+    pub fn render(mut self, mut render: impl FnMut(&mut State, &mut Image) -> String + 'static) -> ! {
+        let event_loop = EventLoop::new();
+        let window = glutin::window::WindowBuilder::new()
+            .with_title(&self.title)
+            .with_inner_size(glutin::dpi::LogicalSize::new(self.width as f64, self.height as f64))
+            .with_resizable(false);
+        let display = Display::new(window, glutin::ContextBuilder::new().with_vsync(true), &event_loop).unwrap();
+
+        let mut image = Image::new(self.width, self.height);
+        let texture = Texture2d::empty_with_format(&display, UncompressedFloatFormat::U8U8U8, MipmapsOption::NoMipmap, self.width as u32, self.height as u32).unwrap();
+
+        let mut next_frame = Instant::now();
+
+        event_loop.run(move |event, _, control_flow| match event {
+            Event::NewEvents(StartCause::ResumeTimeReached { .. }) | Event::NewEvents(StartCause::Init) => {
+                next_frame += FRAME_TIME;
+                *control_flow = ControlFlow::WaitUntil(next_frame);
+
+                let title = render(&mut self.state, &mut image);
+                if title != self.title {
+                    display.gl_window().window().set_title(&title);
+                    self.title = title;
+                }
+
+                texture.write(Rect { left: 0, bottom: 0, width: self.width as u32, height: self.height as u32 }, &image);
+
+                let target = display.draw();
+                texture.as_surface().fill(&target, MagnifySamplerFilter::Linear);
+                target.finish().unwrap();
+            },
+            Event::WindowEvent { event: WindowEvent::CloseRequested, .. } => *control_flow = ControlFlow::Exit,
+            event => {
+                (self.input)(&mut self.state, &event);
+            }
+        })
+    }
+}